@@ -1,36 +1,145 @@
+use std::{collections::HashSet, fmt::Write, time::Duration};
+
 use volty::prelude::*;
 
-use crate::{Bot, error::Error, reaction::RoleAction};
+use crate::{
+    Bot,
+    constants::{HELP_BOT_AUTOROLE_MESSAGE, MAX_AUTOROLE_DELAY},
+    database::{AutoRole, ServerSettings},
+    error::Error,
+    format_duration, parse_duration,
+    reaction::RoleAction,
+    scheduler::unix_time,
+};
+
+pub fn autorole_list(server: &Server, auto_roles: &[AutoRole]) -> String {
+    let mut list = "Current AutoRoles:".to_string();
+    for AutoRole { role_id, delay } in auto_roles {
+        let name = server
+            .roles
+            .get(role_id)
+            .map(|r| &r.name)
+            .unwrap_or(role_id);
+        write!(list, "\n`{name}`").unwrap();
+        if !delay.is_zero() {
+            write!(list, " after {}", format_duration(*delay)).unwrap();
+        }
+    }
+    list
+}
 
 impl Bot {
+    /// Parses `ROLE ROLE after 10m`, a delay applies to the role before it.
+    pub async fn parse_autoroles(
+        &self,
+        server: &Server,
+        user_id: &str,
+        args: &str,
+    ) -> Result<Vec<AutoRole>, Error> {
+        let mut auto_roles: Vec<AutoRole> = Vec::new();
+        let mut args = args.split_ascii_whitespace();
+        while let Some(role_id_or_name) = args.next() {
+            if role_id_or_name.eq_ignore_ascii_case("after") {
+                let delay = args.next().and_then(parse_duration);
+                let (Some(delay), Some(auto_role)) = (delay, auto_roles.last_mut()) else {
+                    return Err(Error::Custom(
+                        "Delays go after a role!\n`ROLE after 10m`".to_string(),
+                    ));
+                };
+                if delay > MAX_AUTOROLE_DELAY {
+                    return Err(Error::Custom(format!(
+                        "Delay must be {} or less!",
+                        format_duration(MAX_AUTOROLE_DELAY)
+                    )));
+                }
+                auto_role.delay = delay;
+                continue;
+            }
+            let role_id = self
+                .assignable_role(server, user_id, role_id_or_name)
+                .await?;
+            auto_roles.push(AutoRole {
+                role_id,
+                delay: Duration::ZERO,
+            });
+        }
+        Ok(auto_roles)
+    }
+
     pub async fn on_member_join(&self, server_id: &str, user_id: &str) -> Result<(), Error> {
         let Some(settings) = self.db.get_settings(server_id).await else {
             return Ok(());
         };
-        if settings.auto_roles.is_empty() {
+        if settings.auto_roles.is_empty() && settings.bot_auto_roles.is_empty() && !settings.sticky
+        {
             return Ok(());
-        };
+        }
         let user = self.cache.fetch_user(&self.http, user_id).await?;
         if user.bot.is_some() {
-            return Ok(());
+            return self.on_bot_join(server_id, user_id, settings).await;
         }
+        let sticky_roles = if settings.sticky {
+            self.db.get_sticky_roles(server_id, user_id).await?
+        } else {
+            Vec::new()
+        };
+        if settings.auto_roles.is_empty() && sticky_roles.is_empty() {
+            return Ok(());
+        };
 
         let Some(server) = self.cache.get_server(server_id).await else {
             return Ok(());
         };
-        let mut roles = settings.auto_roles.clone();
-        roles.retain(|role_id| server.roles.contains_key(role_id));
-        if roles.len() != settings.auto_roles.len() {
-            let mut settings = settings;
-            settings.auto_roles = roles.clone();
+        let mut auto_roles = settings.auto_roles.clone();
+        auto_roles.retain(|r| server.roles.contains_key(&r.role_id));
+        if auto_roles.len() != settings.auto_roles.len() {
+            let mut settings = settings.clone();
+            settings.auto_roles = auto_roles.clone();
             self.db.save_settings(settings).await?;
         }
+        let (delayed, auto_roles): (Vec<_>, Vec<_>) =
+            auto_roles.into_iter().partition(|r| !r.delay.is_zero());
+        let mut roles: Vec<String> = auto_roles.into_iter().map(|r| r.role_id).collect();
 
         let my_id = self.cache.user_id();
         self.check_server_perms(server_id, my_id, &[Permission::AssignRoles])
             .await?;
-        self.check_above_roles(server_id, my_id, roles.iter().map(|s| s.as_str()))
-            .await?;
+
+        if !sticky_roles.is_empty() {
+            let bot_member = self
+                .cache
+                .fetch_member(&self.http, server_id, my_id)
+                .await?;
+            let bot_rank = bot_member.rank(&server);
+            for role_id in &sticky_roles {
+                let is_assignable = server
+                    .roles
+                    .get(role_id)
+                    .is_some_and(|role| role.rank > bot_rank);
+                if is_assignable
+                    && !settings.sticky_exclude.contains(role_id)
+                    && !roles.contains(role_id)
+                {
+                    roles.push(role_id.clone());
+                }
+            }
+        }
+        let delayed_ids = delayed.iter().map(|r| &r.role_id);
+        self.check_above_roles(
+            server_id,
+            my_id,
+            roles.iter().chain(delayed_ids).map(|s| s.as_str()),
+        )
+        .await?;
+
+        let now = unix_time();
+        for AutoRole { role_id, delay } in delayed {
+            let due = now + delay.as_secs() as i64;
+            println!("AutoRole: {server_id}, {user_id}, {role_id} at {due}");
+            self.db
+                .schedule_role(server_id, user_id, &role_id, due)
+                .await?;
+        }
 
         println!("AutoRole: {server_id}, {user_id}, {:?}", &roles);
         if !roles.is_empty() {
@@ -40,13 +149,205 @@ impl Bot {
                 RoleAction {
                     give: roles,
                     remove: vec![],
+                    limit: None,
                 },
             )
             .await;
         }
+        // Only forgotten once queued, so a failed rejoin keeps them for next time.
+        if !sticky_roles.is_empty() {
+            self.db
+                .forget_sticky_roles(server_id, user_id, &sticky_roles)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn on_bot_join(
+        &self,
+        server_id: &str,
+        user_id: &str,
+        settings: ServerSettings,
+    ) -> Result<(), Error> {
+        let Some(server) = self.cache.get_server(server_id).await else {
+            return Ok(());
+        };
+        let mut roles = settings.bot_auto_roles.clone();
+        roles.retain(|role_id| server.roles.contains_key(role_id));
+        if roles.len() != settings.bot_auto_roles.len() {
+            let mut settings = settings;
+            settings.bot_auto_roles = roles.clone();
+            self.db.save_settings(settings).await?;
+        }
+        if roles.is_empty() {
+            return Ok(());
+        }
+
+        let my_id = self.cache.user_id();
+        self.check_server_perms(server_id, my_id, &[Permission::AssignRoles])
+            .await?;
+        self.check_above_roles(server_id, my_id, roles.iter().map(|s| s.as_str()))
+            .await?;
+
+        println!("Bot AutoRole: {server_id}, {user_id}, {:?}", &roles);
+        self.queue_edit(
+            server_id,
+            user_id.to_string(),
+            RoleAction {
+                give: roles,
+                remove: vec![],
+                limit: None,
+            },
+        )
+        .await;
+        Ok(())
+    }
+
+    pub async fn bot_autorole_command(
+        &self,
+        message: &Message,
+        server: &Server,
+        args: &str,
+    ) -> Result<(), Error> {
+        let mut settings = self
+            .db
+            .get_settings(&server.id)
+            .await
+            .unwrap_or_else(|| ServerSettings::new(server.id.clone()));
+        if args.is_empty() {
+            let mut send =
+                HELP_BOT_AUTOROLE_MESSAGE.replace("%BOT_MENTION%", self.cache.user_mention());
+            if !settings.bot_auto_roles.is_empty() {
+                write!(send, "\nCurrent Bot AutoRoles:").unwrap();
+                for role in &settings.bot_auto_roles {
+                    let name = server.roles.get(role).map(|r| &r.name).unwrap_or(role);
+                    write!(send, "\n`{name}`").unwrap();
+                }
+            }
+            self.http.send_message(&message.channel_id, send).await?;
+            return Ok(());
+        }
+
+        self.check_server_perms(&server.id, self.cache.user_id(), &[Permission::AssignRoles])
+            .await?;
+        self.check_server_perms(
+            &server.id,
+            &message.author_id,
+            &[Permission::AssignRoles, Permission::ManageServer],
+        )
+        .await?;
+
+        settings.bot_auto_roles.clear();
+        if args != "clear" {
+            for role_id_or_name in args.split_ascii_whitespace() {
+                let role_id = self
+                    .assignable_role(server, &message.author_id, role_id_or_name)
+                    .await?;
+                settings.bot_auto_roles.push(role_id);
+                if settings.bot_auto_roles.len() > 25 {
+                    self.http
+                        .send_message(&message.channel_id, "No more than 25 bot autoroles!")
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+        self.db.save_settings(settings).await?;
+
+        let send = if args == "clear" {
+            "Bot AutoRole cleared!"
+        } else {
+            "Bot AutoRole set!"
+        };
+        self.http.send_message(&message.channel_id, send).await?;
         Ok(())
     }
 
+    pub async fn autorole_apply_command(
+        &self,
+        message: &Message,
+        server: &Server,
+        args: &str,
+    ) -> Result<(), Error> {
+        let dry_run = args.eq_ignore_ascii_case("--dry-run");
+        let my_id = self.cache.user_id();
+        self.check_server_perms(&server.id, my_id, &[Permission::AssignRoles])
+            .await?;
+        self.check_server_perms(
+            &server.id,
+            &message.author_id,
+            &[Permission::AssignRoles, Permission::ManageServer],
+        )
+        .await?;
+
+        let mut roles: Vec<String> = self
+            .db
+            .get_settings(&server.id)
+            .await
+            .map(|s| s.auto_roles.into_iter().map(|r| r.role_id).collect())
+            .unwrap_or_default();
+        roles.retain(|role_id| server.roles.contains_key(role_id));
+        if roles.is_empty() {
+            self.http
+                .send_message(&message.channel_id, "No AutoRoles set!")
+                .await?;
+            return Ok(());
+        }
+        self.check_above_roles(&server.id, my_id, roles.iter().map(|s| s.as_str()))
+            .await?;
+
+        let bot_member = self
+            .cache
+            .fetch_member(&self.http, &server.id, my_id)
+            .await?;
+        let bot_rank = bot_member.rank(server);
+        let response = self.http.fetch_members(&server.id).await?;
+        let bots: HashSet<&str> = response
+            .users
+            .iter()
+            .filter(|u| u.bot.is_some())
+            .map(|u| u.id.as_str())
+            .collect();
+
+        let mut edits = Vec::new();
+        let mut skipped = 0;
+        for member in &response.members {
+            if bots.contains(member.id.user.as_str()) {
+                continue;
+            }
+            let missing: Vec<String> = roles
+                .iter()
+                .filter(|&r| !member.roles.contains(r))
+                .cloned()
+                .collect();
+            if missing.is_empty() {
+                continue;
+            }
+            if member.rank(server) <= bot_rank {
+                skipped += 1;
+                continue;
+            }
+            let action = RoleAction {
+                give: missing,
+                remove: Vec::new(),
+                limit: None,
+            };
+            edits.push((member.id.user.clone(), action));
+        }
+        println!(
+            "AutoRole apply: {}, {} members, dry run: {dry_run}",
+            &server.id,
+            edits.len()
+        );
+        self.queue_bulk_edits(&message.channel_id, &server.id, edits, skipped, dry_run)
+            .await
+    }
+
+    pub async fn on_member_leave(&self, server_id: &str, user_id: &str) -> Result<(), Error> {
+        self.db.cancel_scheduled_roles(server_id, user_id).await?;
+        self.remember_roles(server_id, user_id).await
+    }
+
     pub async fn on_member_join_error(&self, server_id: &str, user_id: &str, e: Error) {
         dbg!(server_id, user_id, e);
     }
@@ -0,0 +1,155 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use volty::prelude::*;
+
+use crate::{
+    Bot, constants::HELP_BULK_MESSAGE, error::Error, reaction::RoleAction, strip_role_mention,
+};
+
+// Members queued between status message updates.
+const PAGE_SIZE: usize = 100;
+
+#[derive(Clone, Copy)]
+enum BulkTarget<'a> {
+    Everyone,
+    NoRoles,
+    HasRole(&'a str),
+}
+
+impl Bot {
+    /// Queues edits for many members, reporting progress by editing a single status message.
+    /// `skipped` is the number of members that couldn't be edited, shown in the summary.
+    pub async fn queue_bulk_edits(
+        &self,
+        channel_id: &str,
+        server_id: &str,
+        edits: Vec<(String, RoleAction)>,
+        skipped: usize,
+        dry_run: bool,
+    ) -> Result<(), Error> {
+        let total = edits.len();
+        let skipped = if skipped > 0 {
+            format!("\nSkipped {skipped} members ranked above me.")
+        } else {
+            String::new()
+        };
+        if dry_run {
+            let send = format!("Dry run: {total} members would change.{skipped}");
+            self.http.send_message(channel_id, send).await?;
+            return Ok(());
+        }
+
+        let status = self
+            .http
+            .send_message(channel_id, format!("Queueing roles for {total} members..."))
+            .await?;
+        for (index, (user_id, action)) in edits.into_iter().enumerate() {
+            self.queue_edit(server_id, user_id, action).await;
+            let queued = index + 1;
+            if queued % PAGE_SIZE == 0 && queued < total {
+                let content = format!("Queueing roles for {total} members... {queued}/{total}");
+                if let Err(e) = self
+                    .http
+                    .edit_message(channel_id, &status.id, content)
+                    .await
+                {
+                    dbg!(e);
+                }
+            }
+        }
+        let content = format!("Queued roles for {total} members!{skipped}");
+        self.http
+            .edit_message(channel_id, &status.id, content)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn bulk_command(&self, message: &Message, args: &str) -> Result<(), Error> {
+        static RE: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"(?i)^(give|take)\s+(.+?)\s+(?:to|from)\s+(.+)$").unwrap());
+        let Some(server) = self.get_server(&message.channel_id).await else {
+            return Ok(());
+        };
+        let (args, dry_run) = match args.strip_suffix("--dry-run") {
+            Some(args) => (args.trim_end(), true),
+            None => (args, false),
+        };
+        let Some(captures) = RE.captures(args) else {
+            let send = HELP_BULK_MESSAGE.replace("%BOT_MENTION%", self.cache.user_mention());
+            self.http.send_message(&message.channel_id, send).await?;
+            return Ok(());
+        };
+        let give = captures[1].eq_ignore_ascii_case("give");
+        let role_id_or_name = strip_role_mention(captures.get(2).unwrap().as_str());
+        let target = captures.get(3).unwrap().as_str();
+
+        let my_id = self.cache.user_id();
+        self.check_server_perms(&server.id, my_id, &[Permission::AssignRoles])
+            .await?;
+        self.check_server_perms(&server.id, &message.author_id, &[Permission::AssignRoles])
+            .await?;
+        let Some((role_id, _role)) = server.role_by_id_or_name(role_id_or_name) else {
+            return Err(Error::InvalidRole(role_id_or_name.to_string()));
+        };
+        self.check_above_roles(&server.id, my_id, [role_id]).await?;
+        self.check_above_roles(&server.id, &message.author_id, [role_id])
+            .await?;
+
+        let target = match target.to_lowercase().as_str() {
+            "everyone" => BulkTarget::Everyone,
+            "no-roles" if give => BulkTarget::NoRoles,
+            _ => {
+                let target = strip_role_mention(target);
+                let Some((target_id, _role)) = server.role_by_id_or_name(target) else {
+                    return Err(Error::InvalidRole(target.to_string()));
+                };
+                BulkTarget::HasRole(target_id)
+            }
+        };
+
+        let bot_member = self
+            .cache
+            .fetch_member(&self.http, &server.id, my_id)
+            .await?;
+        let bot_rank = bot_member.rank(&server);
+        let members = self.http.fetch_members(&server.id).await?.members;
+        let mut edits = Vec::new();
+        let mut skipped = 0;
+        for member in members {
+            let is_target = match target {
+                BulkTarget::Everyone => true,
+                BulkTarget::NoRoles => member.roles.is_empty(),
+                BulkTarget::HasRole(target_id) => member.roles.contains(target_id),
+            };
+            if !is_target || member.roles.contains(role_id) == give {
+                continue;
+            }
+            if member.rank(&server) <= bot_rank {
+                skipped += 1;
+                continue;
+            }
+            let action = if give {
+                RoleAction {
+                    give: vec![role_id.to_string()],
+                    remove: Vec::new(),
+                    limit: None,
+                }
+            } else {
+                RoleAction {
+                    give: Vec::new(),
+                    remove: vec![role_id.to_string()],
+                    limit: None,
+                }
+            };
+            edits.push((member.id.user, action));
+        }
+        println!(
+            "Bulk: {}, {} {role_id} for {} members, dry run: {dry_run}",
+            &server.id,
+            if give { "give" } else { "take" },
+            edits.len()
+        );
+        self.queue_bulk_edits(&message.channel_id, &server.id, edits, skipped, dry_run)
+            .await
+    }
+}
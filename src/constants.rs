@@ -1,20 +1,53 @@
+use std::time::Duration;
+
+pub const MAX_AUTOROLE_DELAY: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+pub const MAX_ROLE_EXPIRY: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
 pub const HELP_MESSAGE: &str = 
 "Bot needs `AssignRoles` and `React` permissions!
+With `ManageMessages` it also removes reactions for roles members lost or couldn't get.
 The bot can only assign roles lower than it's highest role.
 If a user is ranked above the bot it cannot give them roles.
 
 Set AutoRole:
 %BOT_MENTION% autorole
 
+Keep roles when members leave and rejoin:
+%BOT_MENTION% sticky
+
+Give or take a role from many members:
+%BOT_MENTION% bulk
+
+Give or take roles from a member:
+%BOT_MENTION% give
+%BOT_MENTION% take
+
+Change the roles on a reaction message:
+%BOT_MENTION% edit
+
+Create or export a reaction message as TOML or JSON:
+%BOT_MENTION% create
+%BOT_MENTION% export
+
+Only allow one role across several reaction messages:
+%BOT_MENTION% group
+
 Edit role colours:
 %BOT_MENTION% colour
 
 Create a reaction message:
 %BOT_MENTION% `{ROLE:Rust}` the bot will replace this in the next step.
 You can put roles anywhere `{ROLE:Python}` in the message
+Give several roles with one emoji `{ROLE:Gamer,LFG}`
+Only allow one role per group `{ROLE:Red|colour}` `{ROLE:Blue|colour}`
+Start with `expires 2h` to remove reaction roles after a while.
+Start with `verify` to keep roles when members unreact.
+Start with `reverse` to take roles when members react.
+Start with `requires {ROLE:Verified}` to only let members with that role react.
+Start with `limit 3` to let members pick at most 3 roles.
+Start with `group colours` to only allow one role from every message in the group.
 
-
-You can then react to the message with the emojis you want.
+You can then react to the message with the emojis you want, server emojis and skin tones work too.
 When you're done react with the checkmark.
 
 The above example would look like this when done:
@@ -38,4 +71,86 @@ pub const HELP_AUTOROLE_MESSAGE: &str =
 "Set or clear an AutoRole for this server.
 Usage
 %BOT_MENTION% autorole `ROLE NAME or ID`
-%BOT_MENTION% autorole clear";
+%BOT_MENTION% autorole `ROLE NAME or ID` after `10m`
+%BOT_MENTION% autorole add `ROLE NAME or ID`
+%BOT_MENTION% autorole remove `ROLE NAME or ID`
+%BOT_MENTION% autorole list
+%BOT_MENTION% autorole clear
+%BOT_MENTION% autorole apply `--dry-run`
+Apply gives the AutoRoles to members who already joined.
+%BOT_MENTION% autorole bots";
+
+pub const HELP_BOT_AUTOROLE_MESSAGE: &str =
+"Set or clear AutoRoles given to bots when they join.
+Usage
+%BOT_MENTION% autorole bots `ROLE NAME or ID`
+%BOT_MENTION% autorole bots clear";
+
+pub const HELP_BULK_MESSAGE: &str =
+"Give or take a role from many members at once.
+Usage
+%BOT_MENTION% bulk give `ROLE` to `ROLE`
+%BOT_MENTION% bulk give `ROLE` to everyone
+%BOT_MENTION% bulk give `ROLE` to no-roles
+%BOT_MENTION% bulk take `ROLE` from `ROLE`
+
+Add `--dry-run` to see how many members would change.";
+
+pub const HELP_GIVE_MESSAGE: &str =
+"Give or take roles from a member.
+Usage
+%BOT_MENTION% give `@USER` `ROLE NAME or ID`
+%BOT_MENTION% give `@USER` `ROLE NAME or ID` for `2h`
+%BOT_MENTION% take `@USER` `ROLE NAME or ID`";
+
+pub const HELP_EDIT_MESSAGE: &str =
+"Change the roles on a reaction message without recreating it.
+Usage
+%BOT_MENTION% edit `MESSAGE LINK or ID` set `EMOJI` `ROLE NAME or ID`
+%BOT_MENTION% edit `MESSAGE LINK or ID` set `EMOJI` `ROLE,ROLE`
+%BOT_MENTION% edit `MESSAGE LINK or ID` remove `EMOJI`
+%BOT_MENTION% edit `MESSAGE LINK or ID` resend `EMOJI` `ROLE NAME or ID`
+
+New emojis need `resend`, it sends the message again and members keep their roles but need to react again.";
+
+pub const HELP_CREATE_MESSAGE: &str =
+"Create a reaction message in one step from a TOML or JSON code block.
+Usage
+%BOT_MENTION% create
+```toml
+text = \"Pick your colour\"
+exclusive = true
+limit = 1
+
+[[roles]]
+emoji = \"🔴\"
+role = \"Red\"
+text = \"for red\"
+```
+Options are `exclusive`, `formatted`, `verify`, `reverse`, `expires`, `requires`, `limit`
+and `group` to join a server wide group.
+Roles can also have a `group`, only one role per group can be picked.
+Emojis can be unicode, `:shortcode:` or a custom emoji ID.
+The template can also be attached as a `.toml` or `.json` file.";
+
+pub const HELP_EXPORT_MESSAGE: &str =
+"Export a reaction message as a template for %BOT_MENTION% create.
+Usage
+%BOT_MENTION% export `MESSAGE LINK or ID`
+%BOT_MENTION% export `MESSAGE LINK or ID` json";
+
+pub const HELP_GROUP_MESSAGE: &str =
+"Only allow one role across every reaction message in a group.
+Usage
+%BOT_MENTION% group `MESSAGE LINK or ID` `GROUP NAME`
+%BOT_MENTION% group `MESSAGE LINK or ID` none
+
+New reaction messages can join a group by starting with `group GROUP NAME`.";
+
+pub const HELP_STICKY_MESSAGE: &str =
+"Give members their roles back when they leave and rejoin.
+Only roles below the bot's highest role are kept.
+Usage
+%BOT_MENTION% sticky on
+%BOT_MENTION% sticky off
+%BOT_MENTION% sticky exclude `ROLE NAME or ID`";
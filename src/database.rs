@@ -1,12 +1,185 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::Duration,
+};
 
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension, Row};
 use tokio::sync::{Mutex, RwLock};
 
+use crate::reaction::{RoleAction, RoleLimit, RoleMessage};
+
+// Applied in order, the number of applied migrations is stored in `PRAGMA user_version`.
+// Never edit a released migration, add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: AutoRoles, databases from before migrations only have this table.
+    "CREATE TABLE IF NOT EXISTS autoroles (
+        server_id TEXT NOT NULL,
+        role_id TEXT NOT NULL,
+        PRIMARY KEY (server_id, role_id)
+    );",
+    // 2: Role messages
+    "CREATE TABLE IF NOT EXISTS role_messages (
+        message_id TEXT NOT NULL PRIMARY KEY,
+        channel_id TEXT NOT NULL,
+        server_id TEXT NOT NULL,
+        exclusive INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS role_message_roles (
+        message_id TEXT NOT NULL,
+        emoji TEXT NOT NULL,
+        role_id TEXT NOT NULL,
+        PRIMARY KEY (message_id, emoji)
+    );",
+    // 3: Role edit queue
+    "CREATE TABLE IF NOT EXISTS pending_edits (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        server_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        give_roles TEXT NOT NULL,
+        remove_roles TEXT NOT NULL
+    );",
+    // 4: Sticky roles
+    "CREATE TABLE server_settings (
+        server_id TEXT NOT NULL PRIMARY KEY,
+        sticky INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE sticky_excludes (
+        server_id TEXT NOT NULL,
+        role_id TEXT NOT NULL,
+        PRIMARY KEY (server_id, role_id)
+    );
+    CREATE TABLE sticky_roles (
+        server_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        role_id TEXT NOT NULL,
+        PRIMARY KEY (server_id, user_id, role_id)
+    );",
+    // 5: Delayed AutoRoles, delay in seconds and due as a unix timestamp.
+    "ALTER TABLE autoroles ADD COLUMN delay INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE scheduled_roles (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        server_id TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        role_id TEXT NOT NULL,
+        due INTEGER NOT NULL
+    );
+    CREATE INDEX scheduled_roles_due ON scheduled_roles (due);",
+    // 6: Bot AutoRoles
+    "CREATE TABLE bot_autoroles (
+        server_id TEXT NOT NULL,
+        role_id TEXT NOT NULL,
+        PRIMARY KEY (server_id, role_id)
+    );",
+    // 7: Temporary roles, expiries are scheduled roles with give = 0, expires in seconds.
+    "ALTER TABLE scheduled_roles ADD COLUMN give INTEGER NOT NULL DEFAULT 1;
+    ALTER TABLE role_messages ADD COLUMN expires INTEGER NOT NULL DEFAULT 0;",
+    // 8: Verify role messages
+    "ALTER TABLE role_messages ADD COLUMN verify INTEGER NOT NULL DEFAULT 0;",
+    // 9: Reverse role messages
+    "ALTER TABLE role_messages ADD COLUMN reverse INTEGER NOT NULL DEFAULT 0;",
+    // 10: Role required to use a role message
+    "ALTER TABLE role_messages ADD COLUMN requires TEXT;",
+    // 11: Max roles per member on a role message, queued edits keep the limit.
+    "ALTER TABLE role_messages ADD COLUMN role_limit INTEGER;
+    ALTER TABLE pending_edits ADD COLUMN limit_roles TEXT NOT NULL DEFAULT '';
+    ALTER TABLE pending_edits ADD COLUMN role_limit INTEGER;",
+    // 12: Several roles per emoji
+    "CREATE TABLE role_message_roles_new (
+        message_id TEXT NOT NULL,
+        emoji TEXT NOT NULL,
+        role_id TEXT NOT NULL,
+        PRIMARY KEY (message_id, emoji, role_id)
+    );
+    INSERT INTO role_message_roles_new (message_id, emoji, role_id)
+        SELECT message_id, emoji, role_id FROM role_message_roles;
+    DROP TABLE role_message_roles;
+    ALTER TABLE role_message_roles_new RENAME TO role_message_roles;",
+    // 13: Exclusive groups within a role message
+    "ALTER TABLE role_message_roles ADD COLUMN group_name TEXT;",
+    // 14: Exclusive groups shared by role messages in a server
+    "ALTER TABLE role_messages ADD COLUMN server_group TEXT;",
+    // 15: Reactions last seen on role messages, compared when reconciling
+    "CREATE TABLE IF NOT EXISTS role_message_reactors (
+        message_id TEXT NOT NULL,
+        emoji TEXT NOT NULL,
+        user_id TEXT NOT NULL,
+        PRIMARY KEY (message_id, emoji, user_id)
+    );",
+    // 16: Order of an emoji's roles as written in the message
+    "ALTER TABLE role_message_roles ADD COLUMN position INTEGER NOT NULL DEFAULT 0;",
+];
+
+fn migrate(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    apply_migrations(conn, MIGRATIONS)
+}
+
+// Each migration runs in its own transaction, a failed one leaves the version unchanged.
+fn apply_migrations(conn: &mut Connection, migrations: &[&str]) -> Result<(), rusqlite::Error> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+    for (index, migration) in migrations.iter().enumerate().skip(version) {
+        let version = index + 1;
+        let txn = conn.transaction()?;
+        if let Err(e) = txn.execute_batch(migration) {
+            eprintln!("Database migration {version} failed!");
+            return Err(e);
+        }
+        txn.pragma_update(None, "user_version", version)?;
+        txn.commit()?;
+        println!("Database migrated to version {version}");
+    }
+    Ok(())
+}
+
+// Role message expiries are stored in seconds, 0 if roles don't expire.
+fn expiry(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+// Columns read by `role_message_from_row` in order.
+const ROLE_MESSAGE_COLUMNS: &str = "channel_id, server_id, exclusive, expires, verify, reverse, requires, role_limit, server_group";
+
+fn role_message_from_row(r: &Row, start: usize) -> Result<RoleMessage, rusqlite::Error> {
+    Ok(RoleMessage {
+        channel_id: r.get(start)?,
+        server_id: r.get(start + 1)?,
+        exclusive: r.get(start + 2)?,
+        expires: expiry(r.get(start + 3)?),
+        verify: r.get(start + 4)?,
+        reverse: r.get(start + 5)?,
+        requires: r.get(start + 6)?,
+        limit: r.get(start + 7)?,
+        roles: HashMap::new(),
+        groups: HashMap::new(),
+        server_group: r.get(start + 8)?,
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct AutoRole {
+    pub role_id: String,
+    pub delay: Duration,
+}
+
 #[derive(Clone, Debug)]
 pub struct ServerSettings {
     pub id: String,
-    pub auto_roles: Vec<String>,
+    pub auto_roles: Vec<AutoRole>,
+    pub bot_auto_roles: Vec<String>,
+    pub sticky: bool,
+    pub sticky_exclude: Vec<String>,
+}
+
+impl ServerSettings {
+    pub fn new(id: String) -> Self {
+        Self {
+            id,
+            auto_roles: Vec::new(),
+            bot_auto_roles: Vec::new(),
+            sticky: false,
+            sticky_exclude: Vec::new(),
+        }
+    }
 }
 
 pub struct SqliteDB {
@@ -15,29 +188,55 @@ pub struct SqliteDB {
 }
 
 impl SqliteDB {
-    pub fn new() -> Result<Self, rusqlite::Error> {
-        let conn = Connection::open("roles.sqlite")?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS autoroles (
-                server_id TEXT NOT NULL,
-                role_id TEXT NOT NULL,
-                PRIMARY KEY (server_id, role_id)
-            )",
-            (),
-        )?;
+    pub fn new(path: &Path) -> Result<Self, rusqlite::Error> {
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
 
         let mut servers = HashMap::new();
-        let mut stmt = conn.prepare("SELECT server_id, role_id FROM autoroles")?;
+        let mut stmt = conn.prepare("SELECT server_id, role_id, delay FROM autoroles")?;
+        let rows = stmt.query_map((), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?;
+        for row in rows {
+            let (server_id, role_id, delay): (String, String, u64) = row?;
+            let settings = servers
+                .entry(server_id.clone())
+                .or_insert_with(|| ServerSettings::new(server_id));
+            settings.auto_roles.push(AutoRole {
+                role_id,
+                delay: Duration::from_secs(delay),
+            });
+        }
+        drop(stmt);
+
+        let mut stmt = conn.prepare("SELECT server_id, role_id FROM bot_autoroles")?;
+        let rows = stmt.query_map((), |r| Ok((r.get(0)?, r.get(1)?)))?;
+        for row in rows {
+            let (server_id, role_id): (String, String) = row?;
+            let settings = servers
+                .entry(server_id.clone())
+                .or_insert_with(|| ServerSettings::new(server_id));
+            settings.bot_auto_roles.push(role_id);
+        }
+        drop(stmt);
+
+        let mut stmt = conn.prepare("SELECT server_id, sticky FROM server_settings")?;
+        let rows = stmt.query_map((), |r| Ok((r.get(0)?, r.get(1)?)))?;
+        for row in rows {
+            let (server_id, sticky): (String, bool) = row?;
+            let settings = servers
+                .entry(server_id.clone())
+                .or_insert_with(|| ServerSettings::new(server_id));
+            settings.sticky = sticky;
+        }
+        drop(stmt);
+
+        let mut stmt = conn.prepare("SELECT server_id, role_id FROM sticky_excludes")?;
         let rows = stmt.query_map((), |r| Ok((r.get(0)?, r.get(1)?)))?;
         for row in rows {
             let (server_id, role_id): (String, String) = row?;
             let settings = servers
                 .entry(server_id.clone())
-                .or_insert_with(|| ServerSettings {
-                    id: server_id,
-                    auto_roles: Vec::new(),
-                });
-            settings.auto_roles.push(role_id);
+                .or_insert_with(|| ServerSettings::new(server_id));
+            settings.sticky_exclude.push(role_id);
         }
         drop(stmt);
 
@@ -51,15 +250,46 @@ impl SqliteDB {
     }
 
     pub async fn save_settings(&self, mut server: ServerSettings) -> Result<(), rusqlite::Error> {
-        server.auto_roles.sort();
-        server.auto_roles.dedup();
+        server.auto_roles.sort_by(|a, b| a.role_id.cmp(&b.role_id));
+        server.auto_roles.dedup_by(|a, b| a.role_id == b.role_id);
+        server.bot_auto_roles.sort();
+        server.bot_auto_roles.dedup();
+        server.sticky_exclude.sort();
+        server.sticky_exclude.dedup();
         {
             let mut conn = self.conn.lock().await;
             let txn = conn.transaction()?;
             txn.execute("DELETE FROM autoroles WHERE server_id = ?", (&server.id,))?;
             let mut stmt =
-                txn.prepare("INSERT INTO autoroles (server_id, role_id) VALUES (?, ?)")?;
-            for role_id in &server.auto_roles {
+                txn.prepare("INSERT INTO autoroles (server_id, role_id, delay) VALUES (?, ?, ?)")?;
+            for auto_role in &server.auto_roles {
+                let delay = auto_role.delay.as_secs();
+                stmt.execute((&server.id, &auto_role.role_id, delay))?;
+            }
+            drop(stmt);
+
+            txn.execute(
+                "DELETE FROM bot_autoroles WHERE server_id = ?",
+                (&server.id,),
+            )?;
+            let mut stmt =
+                txn.prepare("INSERT INTO bot_autoroles (server_id, role_id) VALUES (?, ?)")?;
+            for role_id in &server.bot_auto_roles {
+                stmt.execute((&server.id, role_id))?;
+            }
+            drop(stmt);
+
+            txn.execute(
+                "INSERT OR REPLACE INTO server_settings (server_id, sticky) VALUES (?, ?)",
+                (&server.id, server.sticky),
+            )?;
+            txn.execute(
+                "DELETE FROM sticky_excludes WHERE server_id = ?",
+                (&server.id,),
+            )?;
+            let mut stmt =
+                txn.prepare("INSERT INTO sticky_excludes (server_id, role_id) VALUES (?, ?)")?;
+            for role_id in &server.sticky_exclude {
                 stmt.execute((&server.id, role_id))?;
             }
             drop(stmt);
@@ -68,4 +298,531 @@ impl SqliteDB {
         self.servers.write().await.insert(server.id.clone(), server);
         Ok(())
     }
+
+    pub async fn save_sticky_roles(
+        &self,
+        server_id: &str,
+        user_id: &str,
+        roles: &[String],
+    ) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().await;
+        let txn = conn.transaction()?;
+        txn.execute(
+            "DELETE FROM sticky_roles WHERE server_id = ? AND user_id = ?",
+            (server_id, user_id),
+        )?;
+        let mut stmt =
+            txn.prepare("INSERT INTO sticky_roles (server_id, user_id, role_id) VALUES (?, ?, ?)")?;
+        for role_id in roles {
+            stmt.execute((server_id, user_id, role_id))?;
+        }
+        drop(stmt);
+        txn.commit()
+    }
+
+    /// Roles saved when the member left, forgotten once they're given back.
+    pub async fn get_sticky_roles(
+        &self,
+        server_id: &str,
+        user_id: &str,
+    ) -> Result<Vec<String>, rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        let mut stmt =
+            conn.prepare("SELECT role_id FROM sticky_roles WHERE server_id = ? AND user_id = ?")?;
+        let rows = stmt.query_map((server_id, user_id), |r| r.get(0))?;
+        rows.collect()
+    }
+
+    /// Forgets saved roles once they're given back or expire, so a rejoin doesn't give them.
+    pub async fn forget_sticky_roles(
+        &self,
+        server_id: &str,
+        user_id: &str,
+        roles: &[String],
+    ) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().await;
+        let txn = conn.transaction()?;
+        let mut stmt = txn.prepare(
+            "DELETE FROM sticky_roles WHERE server_id = ? AND user_id = ? AND role_id = ?",
+        )?;
+        for role_id in roles {
+            stmt.execute((server_id, user_id, role_id))?;
+        }
+        drop(stmt);
+        txn.commit()
+    }
+
+    pub async fn clear_sticky_roles(&self, server_id: &str) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        conn.execute("DELETE FROM sticky_roles WHERE server_id = ?", (server_id,))?;
+        Ok(())
+    }
+
+    pub async fn schedule_role(
+        &self,
+        server_id: &str,
+        user_id: &str,
+        role_id: &str,
+        due: i64,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "INSERT INTO scheduled_roles (server_id, user_id, role_id, due) VALUES (?, ?, ?, ?)",
+            (server_id, user_id, role_id, due),
+        )?;
+        Ok(())
+    }
+
+    /// Schedules a role to be removed, replacing any earlier expiry for the same role.
+    pub async fn schedule_expiry(
+        &self,
+        server_id: &str,
+        user_id: &str,
+        role_id: &str,
+        due: i64,
+    ) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().await;
+        let txn = conn.transaction()?;
+        txn.execute(
+            "DELETE FROM scheduled_roles
+                WHERE server_id = ? AND user_id = ? AND role_id = ? AND give = 0",
+            (server_id, user_id, role_id),
+        )?;
+        txn.execute(
+            "INSERT INTO scheduled_roles (server_id, user_id, role_id, due, give)
+                VALUES (?, ?, ?, ?, 0)",
+            (server_id, user_id, role_id, due),
+        )?;
+        txn.commit()
+    }
+
+    /// Returns (ID, ServerID, UserID, RoleID, Give) for every role due at or before `now`.
+    /// Give is false for expiries.
+    pub async fn get_due_roles(
+        &self,
+        now: i64,
+    ) -> Result<Vec<(i64, String, String, String, bool)>, rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT id, server_id, user_id, role_id, give FROM scheduled_roles
+                WHERE due <= ? ORDER BY due",
+        )?;
+        let rows = stmt.query_map((now,), |r| {
+            Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?))
+        })?;
+        rows.collect()
+    }
+
+    pub async fn finish_scheduled_roles(&self, ids: &[i64]) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().await;
+        let txn = conn.transaction()?;
+        let mut stmt = txn.prepare("DELETE FROM scheduled_roles WHERE id = ?")?;
+        for id in ids {
+            stmt.execute((id,))?;
+        }
+        drop(stmt);
+        txn.commit()
+    }
+
+    /// Cancels roles that haven't been given yet, expiries are kept.
+    pub async fn cancel_scheduled_roles(
+        &self,
+        server_id: &str,
+        user_id: &str,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "DELETE FROM scheduled_roles WHERE server_id = ? AND user_id = ? AND give = 1",
+            (server_id, user_id),
+        )?;
+        Ok(())
+    }
+
+    pub async fn get_role_message(&self, id: &str) -> Result<Option<RoleMessage>, rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        let Some(mut message) = conn
+            .query_row(
+                &format!("SELECT {ROLE_MESSAGE_COLUMNS} FROM role_messages WHERE message_id = ?"),
+                (id,),
+                |r| role_message_from_row(r, 0),
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        let mut stmt = conn.prepare(
+            "SELECT emoji, role_id, group_name FROM role_message_roles WHERE message_id = ?
+                ORDER BY position, rowid",
+        )?;
+        let rows = stmt.query_map((id,), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?;
+        for row in rows {
+            let (emoji, role_id, group): (String, String, Option<String>) = row?;
+            if let Some(group) = group {
+                message.groups.insert(emoji.clone(), group);
+            }
+            message.roles.entry(emoji).or_default().push(role_id);
+        }
+        Ok(Some(message))
+    }
+
+    pub async fn get_role_messages(&self) -> Result<Vec<(String, RoleMessage)>, rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        let mut messages = HashMap::new();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT message_id, {ROLE_MESSAGE_COLUMNS} FROM role_messages"
+        ))?;
+        let rows = stmt.query_map((), |r| Ok((r.get(0)?, role_message_from_row(r, 1)?)))?;
+        for row in rows {
+            let (message_id, message): (String, RoleMessage) = row?;
+            messages.insert(message_id, message);
+        }
+        drop(stmt);
+
+        let mut stmt = conn.prepare(
+            "SELECT message_id, emoji, role_id, group_name FROM role_message_roles
+                    ORDER BY position, rowid",
+        )?;
+        let rows = stmt.query_map((), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))?;
+        for row in rows {
+            let (message_id, emoji, role_id, group): (String, String, String, Option<String>) =
+                row?;
+            let Some(message) = messages.get_mut(&message_id) else {
+                continue;
+            };
+            if let Some(group) = group {
+                message.groups.insert(emoji.clone(), group);
+            }
+            message.roles.entry(emoji).or_default().push(role_id);
+        }
+        drop(stmt);
+        Ok(messages.into_iter().collect())
+    }
+
+    pub async fn save_role_message(
+        &self,
+        id: &str,
+        message: &RoleMessage,
+    ) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().await;
+        let txn = conn.transaction()?;
+        txn.execute(
+            "INSERT OR REPLACE INTO role_messages
+                (message_id, channel_id, server_id, exclusive, expires, verify, reverse, requires,
+                role_limit, server_group)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            (
+                id,
+                &message.channel_id,
+                &message.server_id,
+                message.exclusive,
+                message.expires.map_or(0, |d| d.as_secs()),
+                message.verify,
+                message.reverse,
+                &message.requires,
+                message.limit,
+                &message.server_group,
+            ),
+        )?;
+        txn.execute("DELETE FROM role_message_roles WHERE message_id = ?", (id,))?;
+        let mut stmt = txn.prepare(
+            "INSERT INTO role_message_roles (message_id, emoji, role_id, group_name, position)
+                VALUES (?, ?, ?, ?, ?)",
+        )?;
+        for (emoji, role_ids) in &message.roles {
+            let group = message.groups.get(emoji);
+            for (position, role_id) in role_ids.iter().enumerate() {
+                stmt.execute((id, emoji, role_id, group, position))?;
+            }
+        }
+        drop(stmt);
+        txn.commit()
+    }
+
+    /// Every role given by role messages in a server's exclusive group.
+    pub async fn get_group_roles(
+        &self,
+        server_id: &str,
+        group: &str,
+    ) -> Result<Vec<String>, rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT r.role_id FROM role_message_roles r
+                JOIN role_messages m ON m.message_id = r.message_id
+                WHERE m.server_id = ? AND m.server_group = ?",
+        )?;
+        let rows = stmt.query_map((server_id, group), |r| r.get(0))?;
+        rows.collect()
+    }
+
+    /// Message and channel IDs of role messages in a server's exclusive group.
+    pub async fn get_group_messages(
+        &self,
+        server_id: &str,
+        group: &str,
+    ) -> Result<Vec<(String, String)>, rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT message_id, channel_id FROM role_messages
+                WHERE server_id = ? AND server_group = ?",
+        )?;
+        let rows = stmt.query_map((server_id, group), |r| Ok((r.get(0)?, r.get(1)?)))?;
+        rows.collect()
+    }
+
+    /// Exclusive groups in a server and how many role messages are in each.
+    pub async fn get_groups(
+        &self,
+        server_id: &str,
+    ) -> Result<Vec<(String, usize)>, rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT server_group, COUNT(*) FROM role_messages
+                WHERE server_id = ? AND server_group IS NOT NULL
+                GROUP BY server_group ORDER BY server_group",
+        )?;
+        let rows = stmt.query_map((server_id,), |r| Ok((r.get(0)?, r.get(1)?)))?;
+        rows.collect()
+    }
+
+    pub async fn delete_role_message(&self, id: &str) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().await;
+        let txn = conn.transaction()?;
+        txn.execute("DELETE FROM role_messages WHERE message_id = ?", (id,))?;
+        txn.execute("DELETE FROM role_message_roles WHERE message_id = ?", (id,))?;
+        txn.execute(
+            "DELETE FROM role_message_reactors WHERE message_id = ?",
+            (id,),
+        )?;
+        txn.commit()
+    }
+
+    /// Reactions last seen on a role message as (Emoji, UserID).
+    pub async fn get_reactors(
+        &self,
+        message_id: &str,
+    ) -> Result<HashSet<(String, String)>, rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        let mut stmt =
+            conn.prepare("SELECT emoji, user_id FROM role_message_reactors WHERE message_id = ?")?;
+        let rows = stmt.query_map((message_id,), |r| Ok((r.get(0)?, r.get(1)?)))?;
+        rows.collect()
+    }
+
+    pub async fn save_reactors(
+        &self,
+        message_id: &str,
+        reactors: &HashSet<(String, String)>,
+    ) -> Result<(), rusqlite::Error> {
+        let mut conn = self.conn.lock().await;
+        let txn = conn.transaction()?;
+        txn.execute(
+            "DELETE FROM role_message_reactors WHERE message_id = ?",
+            (message_id,),
+        )?;
+        let mut stmt = txn.prepare(
+            "INSERT INTO role_message_reactors (message_id, emoji, user_id) VALUES (?, ?, ?)",
+        )?;
+        for (emoji, user_id) in reactors {
+            stmt.execute((message_id, emoji, user_id))?;
+        }
+        drop(stmt);
+        txn.commit()
+    }
+
+    pub async fn add_reactor(
+        &self,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "INSERT OR IGNORE INTO role_message_reactors (message_id, emoji, user_id)
+                VALUES (?, ?, ?)",
+            (message_id, emoji, user_id),
+        )?;
+        Ok(())
+    }
+
+    pub async fn remove_reactor(
+        &self,
+        message_id: &str,
+        emoji: &str,
+        user_id: &str,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "DELETE FROM role_message_reactors WHERE message_id = ? AND emoji = ? AND user_id = ?",
+            (message_id, emoji, user_id),
+        )?;
+        Ok(())
+    }
+
+    pub async fn save_edit(
+        &self,
+        server_id: &str,
+        user_id: &str,
+        action: &RoleAction,
+    ) -> Result<i64, rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        let (limit_roles, role_limit) = match &action.limit {
+            Some(limit) => (limit.roles.join(","), Some(limit.max)),
+            None => (String::new(), None),
+        };
+        conn.execute(
+            "INSERT INTO pending_edits
+                (server_id, user_id, give_roles, remove_roles, limit_roles, role_limit)
+                VALUES (?, ?, ?, ?, ?, ?)",
+            (
+                server_id,
+                user_id,
+                action.give.join(","),
+                action.remove.join(","),
+                limit_roles,
+                role_limit,
+            ),
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Removes the member's edits up to `last_id`, older edits are replaced by newer ones.
+    pub async fn finish_edits(
+        &self,
+        server_id: &str,
+        user_id: &str,
+        last_id: i64,
+    ) -> Result<(), rusqlite::Error> {
+        let conn = self.conn.lock().await;
+        conn.execute(
+            "DELETE FROM pending_edits WHERE server_id = ? AND user_id = ? AND id <= ?",
+            (server_id, user_id, last_id),
+        )?;
+        Ok(())
+    }
+
+    pub async fn get_pending_edits(
+        &self,
+    ) -> Result<Vec<(i64, String, String, RoleAction)>, rusqlite::Error> {
+        fn split(roles: String) -> Vec<String> {
+            roles
+                .split(',')
+                .filter(|r| !r.is_empty())
+                .map(String::from)
+                .collect()
+        }
+
+        let conn = self.conn.lock().await;
+        let mut stmt = conn.prepare(
+            "SELECT id, server_id, user_id, give_roles, remove_roles, limit_roles, role_limit
+                FROM pending_edits ORDER BY id",
+        )?;
+        let rows = stmt.query_map((), |r| {
+            let limit = match r.get(6)? {
+                Some(max) => Some(RoleLimit {
+                    roles: split(r.get(5)?),
+                    max,
+                }),
+                None => None,
+            };
+            Ok((
+                r.get(0)?,
+                r.get(1)?,
+                r.get(2)?,
+                RoleAction {
+                    give: split(r.get(3)?),
+                    remove: split(r.get(4)?),
+                    limit,
+                },
+            ))
+        })?;
+        rows.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Databases from before migrations only have the autoroles table.
+    fn legacy_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE autoroles (
+                server_id TEXT NOT NULL,
+                role_id TEXT NOT NULL,
+                PRIMARY KEY (server_id, role_id)
+            );
+            INSERT INTO autoroles (server_id, role_id) VALUES ('server', 'role');",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn user_version(conn: &Connection) -> usize {
+        conn.pragma_query_value(None, "user_version", |r| r.get(0))
+            .unwrap()
+    }
+
+    fn table_exists(conn: &Connection, name: &str) -> bool {
+        conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+            (name,),
+            |r| r.get::<_, i64>(0),
+        )
+        .unwrap()
+            > 0
+    }
+
+    #[test]
+    fn migrates_legacy_database() {
+        let mut conn = legacy_connection();
+        assert_eq!(user_version(&conn), 0);
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+
+        let mut stmt = conn
+            .prepare("SELECT server_id, role_id, delay FROM autoroles")
+            .unwrap();
+        let rows: Vec<(String, String, u64)> = stmt
+            .query_map((), |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        drop(stmt);
+        assert_eq!(rows, [("server".to_string(), "role".to_string(), 0)]);
+
+        for table in [
+            "role_messages",
+            "role_message_roles",
+            "pending_edits",
+            "server_settings",
+            "sticky_excludes",
+            "sticky_roles",
+            "scheduled_roles",
+            "bot_autoroles",
+            "role_message_reactors",
+        ] {
+            assert!(table_exists(&conn, table), "missing table {table}");
+        }
+
+        // Already migrated databases are left alone.
+        migrate(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn failed_migration_rolls_back() {
+        let mut conn = legacy_connection();
+        let migrations = [
+            MIGRATIONS[0],
+            "CREATE TABLE broken (id INTEGER);
+            NOT VALID SQL;",
+        ];
+
+        assert!(apply_migrations(&mut conn, &migrations).is_err());
+        assert_eq!(user_version(&conn), 1);
+        assert!(!table_exists(&conn, "broken"));
+    }
 }
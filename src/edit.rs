@@ -0,0 +1,259 @@
+use std::{collections::HashSet, fmt::Write};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use volty::prelude::*;
+
+use crate::{
+    Bot,
+    constants::{HELP_EDIT_MESSAGE, HELP_GROUP_MESSAGE},
+    error::Error,
+    parse_message_link,
+    reaction::{emoji_key, emoji_text, reaction_emoji, valid_group},
+};
+
+impl Bot {
+    /// Adds, removes or re-maps emojis on a finished role message.
+    /// Reactions can't be changed after sending, new emojis need `resend` to send the message again.
+    pub async fn edit_command(&self, message: &Message, args: &str) -> Result<(), Error> {
+        let Some(server) = self.get_server(&message.channel_id).await else {
+            return Ok(());
+        };
+        let mut args = args.splitn(4, char::is_whitespace);
+        let link = args.next().and_then(parse_message_link);
+        let command = args.next().unwrap_or_default().to_lowercase();
+        let emoji = args.next().unwrap_or_default().trim_matches(':');
+        let role_id_or_name = args.next().unwrap_or_default().trim();
+        let is_valid = match command.as_str() {
+            "add" | "set" | "resend" => !emoji.is_empty() && !role_id_or_name.is_empty(),
+            "remove" => !emoji.is_empty(),
+            _ => false,
+        };
+        let (Some((link_channel_id, message_id)), true) = (link, is_valid) else {
+            let send = HELP_EDIT_MESSAGE.replace("%BOT_MENTION%", self.cache.user_mention());
+            self.http.send_message(&message.channel_id, send).await?;
+            return Ok(());
+        };
+
+        let my_id = self.cache.user_id();
+        self.check_server_perms(&server.id, my_id, &[Permission::AssignRoles])
+            .await?;
+        self.check_server_perms(&server.id, &message.author_id, &[Permission::AssignRoles])
+            .await?;
+
+        let (channel_id, mut role_message) = self
+            .find_role_message(&server.id, link_channel_id, message_id, &message.channel_id)
+            .await?;
+        let target = self
+            .cache
+            .fetch_message(&self.http, &channel_id, message_id)
+            .await?;
+        let (Some(content), Some(interactions)) = (&target.content, &target.interactions) else {
+            return Err(Error::Custom("Role message not found!".to_string()));
+        };
+        self.check_role_message(&server.id, &message.author_id, &role_message)
+            .await?;
+
+        let reaction = reaction_emoji(emoji);
+        let emoji = emoji_key(emoji).to_string();
+        let text = emoji_text(&emoji);
+        let old_roles = role_message.roles.get(&emoji).cloned();
+        let no_match = || {
+            Error::Custom(format!(
+                "Couldn't find {text} in the message, it may have been edited by hand!"
+            ))
+        };
+        // New emojis can't be allowed on a sent message, so it's sent again with them.
+        // Members lose their reactions, so it's only done when asked for with `resend`.
+        let mut reactions: Vec<&str> = interactions
+            .reactions
+            .iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        let mut is_resend = false;
+        let (content, send) = if command == "remove" {
+            let Some(old_roles) = old_roles else {
+                return Err(Error::Custom(format!("{text} isn't used on that message!")));
+            };
+            let content = replace_roles(content, &emoji, &old_roles, None).ok_or_else(no_match)?;
+            role_message.roles.remove(&emoji);
+            role_message.groups.remove(&emoji);
+            (content, format!("{text} removed!"))
+        } else {
+            if !reactions.iter().any(|&r| emoji_key(r) == emoji) {
+                if command != "resend" {
+                    return Err(Error::Custom(format!(
+                        "{text} isn't on the message yet, adding it sends the message again and members lose their reactions.\nUse `resend` instead of `{command}` to do it anyway."
+                    )));
+                }
+                if reactions.len() >= 20 {
+                    return Err(Error::Custom(
+                        "No more than 20 emojis per message!".to_string(),
+                    ));
+                }
+                reactions.push(reaction);
+                is_resend = true;
+            }
+            let mut role_ids = Vec::new();
+            let mut names = Vec::new();
+            for role_id_or_name in role_id_or_name.split(',') {
+                let role_id = self
+                    .assignable_role(&server, &message.author_id, role_id_or_name.trim())
+                    .await?;
+                names.push(server.roles.get(&role_id).unwrap().name.as_str());
+                role_ids.push(role_id);
+            }
+            let (new_roles, names) = (role_ids.join(","), names.join(", "));
+            let content = match old_roles {
+                Some(old_roles) => {
+                    replace_roles(content, &emoji, &old_roles, Some((&new_roles, &names)))
+                        .ok_or_else(no_match)?
+                }
+                None if is_formatted(content) => format!("{content}\n{text}[]({new_roles})"),
+                None => format!("{content}\n{text}[]({new_roles}) __{names}__"),
+            };
+            role_message.roles.insert(emoji.clone(), role_ids);
+            (content, format!("{text} set to `{names}`!"))
+        };
+        if content.len() > 2_000 {
+            return Err(Error::Custom("Message would be too long!".to_string()));
+        }
+
+        let send = if is_resend {
+            let new_id = self
+                .send_role_message(content, reactions, role_message)
+                .await?;
+            println!(
+                "Edit: Server: {}, Message: {message_id} re-sent as {new_id}, By: {}, {command} {text}",
+                &server.id, &message.author_id
+            );
+            let send = format!(
+                "{send}\nThe message was sent again to allow the new emoji, members keep their roles but need to react again."
+            );
+            // The old message keeps working until it's gone, so it's only forgotten once deleted.
+            match self.http.delete_message(&channel_id, message_id).await {
+                Ok(_) => {
+                    self.role_messages.write().await.remove(message_id);
+                    self.db.delete_role_message(message_id).await?;
+                    send
+                }
+                Err(e) => {
+                    dbg!(e);
+                    format!(
+                        "{send}\nThe old message couldn't be deleted, delete it so members only react to the new one!"
+                    )
+                }
+            }
+        } else {
+            self.http
+                .edit_message(&channel_id, message_id, content)
+                .await?;
+            self.db.save_role_message(message_id, &role_message).await?;
+            self.role_messages
+                .write()
+                .await
+                .insert(message_id.to_string(), role_message);
+            println!(
+                "Edit: Server: {}, Message: {message_id}, By: {}, {command} {text}",
+                &server.id, &message.author_id
+            );
+            send
+        };
+        self.http.send_message(&message.channel_id, send).await?;
+        Ok(())
+    }
+
+    /// Adds a role message to a server wide exclusive group, or takes it out.
+    pub async fn group_command(&self, message: &Message, args: &str) -> Result<(), Error> {
+        let Some(server) = self.get_server(&message.channel_id).await else {
+            return Ok(());
+        };
+        let mut args = args.split_ascii_whitespace();
+        let link = args.next().and_then(parse_message_link);
+        let group = match args.next() {
+            Some(name) if name.eq_ignore_ascii_case("none") => Some(None),
+            Some(name) => valid_group(name).map(Some),
+            None => None,
+        };
+        let (Some((link_channel_id, message_id)), Some(group)) = (link, group) else {
+            let mut send = HELP_GROUP_MESSAGE.replace("%BOT_MENTION%", self.cache.user_mention());
+            let groups = self.db.get_groups(&server.id).await?;
+            if !groups.is_empty() {
+                send.push_str("\n\nGroups:");
+                for (name, count) in groups {
+                    write!(send, "\n`{name}` {count} message(s)").unwrap();
+                }
+            }
+            self.http.send_message(&message.channel_id, send).await?;
+            return Ok(());
+        };
+
+        self.check_server_perms(&server.id, &message.author_id, &[Permission::AssignRoles])
+            .await?;
+        let (_, mut role_message) = self
+            .find_role_message(&server.id, link_channel_id, message_id, &message.channel_id)
+            .await?;
+        self.check_role_message(&server.id, &message.author_id, &role_message)
+            .await?;
+
+        let send = match &group {
+            Some(name) => format!("Message added to the `{name}` group!"),
+            None => "Message removed from its group!".to_string(),
+        };
+        role_message.server_group = group;
+        self.db.save_role_message(message_id, &role_message).await?;
+        println!(
+            "Group: Server: {}, Message: {message_id}, By: {}, Group: {:?}",
+            &server.id, &message.author_id, &role_message.server_group
+        );
+        self.role_messages
+            .write()
+            .await
+            .insert(message_id.to_string(), role_message);
+        self.http.send_message(&message.channel_id, send).await?;
+        Ok(())
+    }
+}
+
+/// Replaces or removes an emoji's roles in role message content, role names are kept in sync.
+/// Roles are comma separated IDs and names, the emoji keeps its group.
+/// The old roles can be in any order, returns `None` if they aren't in the content.
+fn replace_roles(
+    content: &str,
+    emoji: &str,
+    old_roles: &[String],
+    new_roles: Option<(&str, &str)>,
+) -> Option<String> {
+    let emoji = emoji_text(emoji);
+    let pattern = format!(
+        r"{}\[]\(([^)#\n]+)(#[a-z0-9_-]+)?\)( __[^\n]*?__)?",
+        regex::escape(&emoji)
+    );
+    let re = Regex::new(&pattern).unwrap();
+    let old_roles: HashSet<&str> = old_roles.iter().map(String::as_str).collect();
+    let captures = re.captures_iter(content).find(|captures| {
+        let role_ids: HashSet<&str> = captures[1].split(',').collect();
+        role_ids == old_roles
+    })?;
+    let group = captures.get(2).map_or("", |g| g.as_str());
+    let replacement = match new_roles {
+        Some((role_ids, names)) if captures.get(3).is_some() => {
+            format!("{emoji}[]({role_ids}{group}) __{names}__")
+        }
+        Some((role_ids, _)) => format!("{emoji}[]({role_ids}{group})"),
+        None => String::new(),
+    };
+    let range = captures.get(0).unwrap().range();
+    Some(format!(
+        "{}{replacement}{}",
+        &content[..range.start],
+        &content[range.end..]
+    ))
+}
+
+/// Formatted messages don't follow roles with their names.
+fn is_formatted(content: &str) -> bool {
+    static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[]\([^)\n]*\) __").unwrap());
+    !RE.is_match(content)
+}
@@ -12,6 +12,9 @@ pub enum Error {
 
     UserMissing(Permission),
     UserRankTooLow(String),
+    MemberRankAboveUser,
+    RequiredRoleMissing(String),
+    RoleLimitReached(usize),
     InvalidUser,
 
     Http(HttpError),
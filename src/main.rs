@@ -1,15 +1,25 @@
 use std::{
     collections::HashMap,
     fmt::Write,
+    path::PathBuf,
     sync::{Arc, atomic::AtomicBool},
+    time::Duration,
 };
 
+use autorole::autorole_list;
 use database::ServerSettings;
 use once_cell::sync::Lazy;
-use reaction::{RoleMessage, RoleReact, ServerSender, SetupMessage};
+use reaction::{RoleMessage, RoleReact, ServerHandle, SetupMessage};
 use regex::Regex;
-use signal_hook::{consts::signal::*, flag::register_conditional_shutdown};
-use tokio::sync::RwLock;
+use signal_hook::{
+    consts::signal::*,
+    flag::{register, register_conditional_shutdown},
+};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::{RwLock, watch},
+    time::Instant,
+};
 use volty::{
     http::routes::{servers::role_edit::RoleEdit, users::user_edit::UserEdit},
     prelude::*,
@@ -17,10 +27,17 @@ use volty::{
 };
 
 mod autorole;
+mod bulk;
 mod constants;
 mod database;
+mod edit;
 mod error;
+mod manual;
 mod reaction;
+mod reconcile;
+mod scheduler;
+mod sticky;
+mod template;
 
 use constants::*;
 use error::Error;
@@ -39,15 +56,84 @@ fn parse_colours(colours: &str) -> String {
     colours.to_string()
 }
 
+/// Parses durations like `10m`, `2h` or `1d12h`.
+fn parse_duration(duration: &str) -> Option<Duration> {
+    static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)(\d+)([smhdw])").unwrap());
+    let mut seconds: u64 = 0;
+    let mut end = 0;
+    for capture in RE.captures_iter(duration) {
+        let range = capture.get(0).unwrap().range();
+        if range.start != end {
+            return None;
+        }
+        end = range.end;
+        let amount: u64 = capture[1].parse().ok()?;
+        let unit = match capture[2].to_ascii_lowercase().as_str() {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => 7 * 24 * 60 * 60,
+        };
+        seconds = seconds.checked_add(amount.checked_mul(unit)?)?;
+    }
+    if end == 0 || end != duration.len() {
+        return None;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    let mut formatted = String::new();
+    for (unit, size) in [
+        ("w", 7 * 24 * 60 * 60),
+        ("d", 24 * 60 * 60),
+        ("h", 60 * 60),
+        ("m", 60),
+        ("s", 1),
+    ] {
+        if seconds >= size {
+            write!(formatted, "{}{unit}", seconds / size).unwrap();
+            seconds %= size;
+        }
+    }
+    formatted
+}
+
+/// Strips a role mention down to the role ID.
+fn strip_role_mention(arg: &str) -> &str {
+    RE_ROLE_MENTION
+        .captures(arg)
+        .map_or(arg, |c| c.get(1).unwrap().as_str())
+}
+
+/// Parses a message link or ID into the channel ID if linked and the message ID.
+fn parse_message_link(arg: &str) -> Option<(Option<&str>, &str)> {
+    static RE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"^(?:\S*/channel/([0-9A-HJKMNP-TV-Z]{26})/)?([0-9A-HJKMNP-TV-Z]{26})$").unwrap()
+    });
+    let captures = RE.captures(arg)?;
+    let channel_id = captures.get(1).map(|c| c.as_str());
+    Some((channel_id, captures.get(2).unwrap().as_str()))
+}
+
 struct Bot {
     http: Http,
     cache: Cache,
-    db: SqliteDB,
+    db: Arc<SqliteDB>,
 
     setup_messages: RwLock<HashMap<String, SetupMessage>>,
     role_messages: RwLock<HashMap<String, RoleMessage>>,
-
-    server_handlers: RwLock<HashMap<String, ServerSender>>,
+    // k=(ServerID, UserID), members that left before their roles were saved
+    leaving_members: RwLock<HashMap<(String, String), Member>>,
+    // k=(MessageID, UserID, Emoji), v=When the bot took the reaction off, its unreact is ignored
+    removed_reactions: RwLock<HashMap<(String, String, String), Instant>>,
+
+    server_handlers: RwLock<HashMap<String, ServerHandle>>,
+    closing: AtomicBool,
+    stop: watch::Sender<bool>,
+    ready: watch::Sender<bool>,
 }
 
 impl Bot {
@@ -102,6 +188,24 @@ impl Bot {
         Ok(())
     }
 
+    /// Resolves a role that both the bot and `user_id` can assign.
+    async fn assignable_role(
+        &self,
+        server: &Server,
+        user_id: &str,
+        role_id_or_name: &str,
+    ) -> Result<String, Error> {
+        let role_id_or_name = strip_role_mention(role_id_or_name);
+        let Some((role_id, _role)) = server.role_by_id_or_name(role_id_or_name) else {
+            return Err(Error::InvalidRole(role_id_or_name.to_string()));
+        };
+        self.check_above_roles(&server.id, self.cache.user_id(), [role_id])
+            .await?;
+        self.check_above_roles(&server.id, user_id, [role_id])
+            .await?;
+        Ok(role_id.to_string())
+    }
+
     async fn get_server(&self, channel_id: &str) -> Option<Server> {
         let channel = self.cache.get_channel(channel_id).await?;
         self.cache.get_server(channel.server_id()?).await
@@ -151,6 +255,30 @@ impl Bot {
             "color" | "colour" => {
                 return self.colour_command(message, rest).await;
             }
+            "sticky" => {
+                return self.sticky_command(message, rest).await;
+            }
+            "bulk" => {
+                return self.bulk_command(message, rest).await;
+            }
+            "give" => {
+                return self.give_command(message, rest, true).await;
+            }
+            "take" => {
+                return self.give_command(message, rest, false).await;
+            }
+            "edit" => {
+                return self.edit_command(message, rest).await;
+            }
+            "create" => {
+                return self.create_command(message, rest).await;
+            }
+            "export" => {
+                return self.export_command(message, rest).await;
+            }
+            "group" => {
+                return self.group_command(message, rest).await;
+            }
             _ => {}
         }
         let Some(setup) = SetupMessage::parse(message.author_id.clone(), content) else {
@@ -199,7 +327,15 @@ impl Bot {
             Error::UserRankTooLow(role) => {
                 format!("You can only assign roles below your own!\n{role}")
             }
-            Error::MemberRankTooHigh | Error::InvalidUser => unreachable!(),
+            Error::MemberRankTooHigh => {
+                "I can't assign roles to members ranked above me!".to_string()
+            }
+            Error::MemberRankAboveUser => {
+                "You can't assign roles to members ranked above you!".to_string()
+            }
+            Error::RequiredRoleMissing(_) | Error::RoleLimitReached(_) | Error::InvalidUser => {
+                unreachable!()
+            }
             Error::Http(_) => return,
         };
 
@@ -226,17 +362,11 @@ impl Bot {
                 .await?;
             return Ok(());
         }
-        let (mut role_id_or_name, rest) =
-            args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let (role_id_or_name, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
         let Some(server) = self.get_server(&message.channel_id).await else {
             return Ok(());
         };
-        if let Some(role_id) = RE_ROLE_MENTION
-            .captures(role_id_or_name)
-            .map(|c| c.get(1).unwrap().as_str())
-        {
-            role_id_or_name = role_id;
-        }
+        let role_id_or_name = strip_role_mention(role_id_or_name);
         let Some((role_id, _role)) = server.role_by_id_or_name(role_id_or_name) else {
             return Err(Error::InvalidRole(role_id_or_name.to_string()));
         };
@@ -273,22 +403,46 @@ impl Bot {
         let Some(server) = self.get_server(&message.channel_id).await else {
             return Ok(());
         };
+        let settings = self.db.get_settings(&server.id).await;
         if args.is_empty() {
             let mut send =
                 HELP_AUTOROLE_MESSAGE.replace("%BOT_MENTION%", self.cache.user_mention());
-            if let Some(settings) = self.db.get_settings(&server.id).await
+            if let Some(settings) = &settings
                 && !settings.auto_roles.is_empty()
             {
-                write!(send, "\nCurrent AutoRoles:").unwrap();
-
-                for role in settings.auto_roles {
-                    let name = server.roles.get(&role).map(|r| &r.name).unwrap_or(&role);
-                    write!(send, "\n`{name}`").unwrap();
-                }
+                write!(send, "\n{}", autorole_list(&server, &settings.auto_roles)).unwrap();
             }
             self.http.send_message(&message.channel_id, send).await?;
             return Ok(());
         }
+        let (subcommand, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let rest = rest.trim();
+        let subcommand = subcommand.to_lowercase();
+        match subcommand.as_str() {
+            "bots" => {
+                return self.bot_autorole_command(message, &server, rest).await;
+            }
+            "apply" => {
+                return self.autorole_apply_command(message, &server, rest).await;
+            }
+            "list" => {
+                let send = match &settings {
+                    Some(settings) if !settings.auto_roles.is_empty() => {
+                        autorole_list(&server, &settings.auto_roles)
+                    }
+                    _ => "No AutoRoles set!".to_string(),
+                };
+                self.http.send_message(&message.channel_id, send).await?;
+                return Ok(());
+            }
+            "add" | "remove" if rest.is_empty() => {
+                let send =
+                    HELP_AUTOROLE_MESSAGE.replace("%BOT_MENTION%", self.cache.user_mention());
+                self.http.send_message(&message.channel_id, send).await?;
+                return Ok(());
+            }
+            _ => {}
+        }
 
         self.check_server_perms(&server.id, self.cache.user_id(), &[Permission::AssignRoles])
             .await?;
@@ -299,43 +453,58 @@ impl Bot {
         )
         .await?;
 
-        let mut settings = ServerSettings {
-            id: server.id.clone(),
-            auto_roles: Vec::new(),
-        };
-        if args != "clear" {
-            for mut role_id_or_name in args.split_ascii_whitespace() {
-                if let Some(role_id) = RE_ROLE_MENTION
-                    .captures(role_id_or_name)
-                    .map(|c| c.get(1).unwrap().as_str())
-                {
-                    role_id_or_name = role_id;
-                }
-
-                let Some((role_id, _role)) = server.role_by_id_or_name(role_id_or_name) else {
-                    return Err(Error::InvalidRole(role_id_or_name.to_string()));
-                };
-                self.check_above_roles(&server.id, self.cache.user_id(), [role_id])
-                    .await?;
-                self.check_above_roles(&server.id, &message.author_id, [role_id])
+        let mut settings = settings.unwrap_or_else(|| ServerSettings::new(server.id.clone()));
+        let send = match subcommand.as_str() {
+            "clear" if rest.is_empty() => {
+                settings.auto_roles.clear();
+                "AutoRole cleared!"
+            }
+            "add" => {
+                let auto_roles = self
+                    .parse_autoroles(&server, &message.author_id, rest)
                     .await?;
-
-                settings.auto_roles.push(role_id.to_string());
-                if settings.auto_roles.len() > 25 {
-                    self.http
-                        .send_message(&message.channel_id, "No more than 25 autoroles!")
-                        .await?;
-                    return Ok(());
+                for auto_role in auto_roles {
+                    settings
+                        .auto_roles
+                        .retain(|r| r.role_id != auto_role.role_id);
+                    settings.auto_roles.push(auto_role);
+                }
+                "AutoRole added!"
+            }
+            "remove" => {
+                for role_id_or_name in rest.split_ascii_whitespace() {
+                    let role_id_or_name = strip_role_mention(role_id_or_name);
+                    // Deleted roles can still be removed by ID.
+                    let role_id = server
+                        .role_by_id_or_name(role_id_or_name)
+                        .map_or(role_id_or_name, |(role_id, _role)| role_id);
+                    if !settings.auto_roles.iter().any(|r| r.role_id == role_id) {
+                        return Err(Error::Custom(format!(
+                            "Role isn't an AutoRole!\n{role_id_or_name}"
+                        )));
+                    }
+                    if server.roles.contains_key(role_id) {
+                        self.check_above_roles(&server.id, &message.author_id, [role_id])
+                            .await?;
+                    }
+                    settings.auto_roles.retain(|r| r.role_id != role_id);
                 }
+                "AutoRole removed!"
+            }
+            _ => {
+                settings.auto_roles = self
+                    .parse_autoroles(&server, &message.author_id, args)
+                    .await?;
+                "AutoRole set!"
             }
+        };
+        if settings.auto_roles.len() > 25 {
+            self.http
+                .send_message(&message.channel_id, "No more than 25 autoroles!")
+                .await?;
+            return Ok(());
         }
         self.db.save_settings(settings).await?;
-
-        let send = if args == "clear" {
-            "AutoRole cleared!"
-        } else {
-            "AutoRole set!"
-        };
         self.http.send_message(&message.channel_id, send).await?;
         Ok(())
     }
@@ -368,6 +537,9 @@ impl RawHandler for Bot {
                 dbg!(e);
             }
         }
+
+        self.ready.send_replace(true);
+        self.reconcile_role_messages().await;
     }
 
     async fn on_message(&self, message: Message) {
@@ -379,6 +551,9 @@ impl RawHandler for Bot {
     async fn on_message_delete(&self, id: String, _channel_id: String) {
         self.setup_messages.write().await.remove(&id);
         self.role_messages.write().await.remove(&id);
+        if let Err(e) = self.db.delete_role_message(&id).await {
+            dbg!(e);
+        }
     }
 
     async fn on_message_react(
@@ -417,6 +592,12 @@ impl RawHandler for Bot {
             self.on_member_join_error(&id, user_id, e).await;
         }
     }
+
+    async fn on_server_member_leave(&self, id: String, user_id: String) {
+        if let Err(e) = self.on_member_leave(&id, &user_id).await {
+            dbg!(&id, &user_id, e);
+        }
+    }
 }
 
 #[tokio::main]
@@ -428,16 +609,46 @@ async fn main() {
     }
     env_logger::init();
 
-    register_conditional_shutdown(SIGINT, 0, AtomicBool::new(true).into()).unwrap();
-    register_conditional_shutdown(SIGQUIT, 0, AtomicBool::new(true).into()).unwrap();
-    register_conditional_shutdown(SIGTERM, 0, AtomicBool::new(true).into()).unwrap();
-
-    let db = SqliteDB::new().unwrap();
+    // The first signal starts a graceful shutdown, a second one exits immediately.
+    let term_now = Arc::new(AtomicBool::new(false));
+    for sig in [SIGINT, SIGQUIT, SIGTERM] {
+        register_conditional_shutdown(sig, 1, term_now.clone()).unwrap();
+        register(sig, term_now.clone()).unwrap();
+    }
+    let mut sigint = signal(SignalKind::interrupt()).unwrap();
+    let mut sigquit = signal(SignalKind::quit()).unwrap();
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
 
     let cache = Cache::new();
 
     let token = std::env::var("BOT_TOKEN").expect("Missing Env Variable: BOT_TOKEN");
     let api_url = std::env::var("API_URL").unwrap_or_else(|_| String::new());
+    let database_path = match std::env::var("DATABASE_PATH") {
+        Ok(path) if !path.is_empty() => PathBuf::from(path),
+        _ => std::env::var("DATA_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_default()
+            .join("roles.sqlite"),
+    };
+    let shutdown_timeout = std::env::var("SHUTDOWN_TIMEOUT")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .map_or(Duration::from_secs(30), Duration::from_secs);
+
+    if let Some(dir) = database_path.parent()
+        && !dir.as_os_str().is_empty()
+        && let Err(e) = std::fs::create_dir_all(dir)
+    {
+        eprintln!("Unable to create data directory {}: {e}", dir.display());
+        std::process::exit(1);
+    }
+    let db = match SqliteDB::new(&database_path) {
+        Ok(db) => Arc::new(db),
+        Err(e) => {
+            eprintln!("Unable to open database {}: {e}", database_path.display());
+            std::process::exit(1);
+        }
+    };
 
     let (http, ws) = if api_url.is_empty() {
         let http = Http::new(&token, true);
@@ -456,16 +667,30 @@ async fn main() {
         db,
         setup_messages: RwLock::new(HashMap::new()),
         role_messages: RwLock::new(HashMap::new()),
+        leaving_members: RwLock::new(HashMap::new()),
+        removed_reactions: RwLock::new(HashMap::new()),
         server_handlers: RwLock::new(HashMap::new()),
+        closing: AtomicBool::new(false),
+        stop: watch::channel(false).0,
+        ready: watch::channel(false).0,
     };
     let handler = Arc::new(bot);
+    handler.replay_edits().await;
+    tokio::spawn(handler.clone().run_scheduler());
 
     loop {
-        let event = ws.next().await;
+        let event = tokio::select! {
+            event = ws.next() => event,
+            _ = sigint.recv() => break,
+            _ = sigquit.recv() => break,
+            _ = sigterm.recv() => break,
+        };
+        handler.before_cache_update(&event).await;
         cache.update(event.clone()).await;
         let h = handler.clone();
         tokio::spawn(async move {
             h.on_event(event).await;
         });
     }
+    handler.shutdown(shutdown_timeout).await;
 }
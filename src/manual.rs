@@ -0,0 +1,136 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use volty::prelude::*;
+
+use crate::{
+    Bot,
+    constants::{HELP_GIVE_MESSAGE, MAX_ROLE_EXPIRY},
+    error::Error,
+    format_duration, parse_duration,
+    reaction::RoleAction,
+    scheduler::unix_time,
+};
+
+impl Bot {
+    /// Gives or takes roles from a single member, `give` is false for take.
+    pub async fn give_command(
+        &self,
+        message: &Message,
+        args: &str,
+        give: bool,
+    ) -> Result<(), Error> {
+        static RE_USER: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"^(?:<@([0-9A-HJKMNP-TV-Z]{26})>|([0-9A-HJKMNP-TV-Z]{26}))$").unwrap()
+        });
+        let Some(server) = self.get_server(&message.channel_id).await else {
+            return Ok(());
+        };
+        let mut args = args.split_ascii_whitespace();
+        let user_id = args.next().and_then(|a| RE_USER.captures(a)).map(|c| {
+            c.get(1)
+                .unwrap_or_else(|| c.get(2).unwrap())
+                .as_str()
+                .to_string()
+        });
+        let mut role_args: Vec<&str> = args.collect();
+        let mut expires = None;
+        let duration = match role_args[..] {
+            [.., word, duration] if word.eq_ignore_ascii_case("for") => Some(duration),
+            _ => None,
+        };
+        if let Some(duration) = duration {
+            let Some(duration) = parse_duration(duration) else {
+                return Err(Error::Custom(
+                    "Expiry must be a duration!\n`ROLE for 2h`".to_string(),
+                ));
+            };
+            if !give {
+                return Err(Error::Custom("Only given roles can expire!".to_string()));
+            }
+            if duration > MAX_ROLE_EXPIRY {
+                return Err(Error::Custom(format!(
+                    "Expiry must be {} or less!",
+                    format_duration(MAX_ROLE_EXPIRY)
+                )));
+            }
+            expires = Some(duration);
+            role_args.truncate(role_args.len() - 2);
+        }
+        let (Some(user_id), false) = (user_id, role_args.is_empty()) else {
+            let send = HELP_GIVE_MESSAGE.replace("%BOT_MENTION%", self.cache.user_mention());
+            self.http.send_message(&message.channel_id, send).await?;
+            return Ok(());
+        };
+
+        let my_id = self.cache.user_id();
+        self.check_server_perms(&server.id, my_id, &[Permission::AssignRoles])
+            .await?;
+        self.check_server_perms(&server.id, &message.author_id, &[Permission::AssignRoles])
+            .await?;
+
+        let mut roles = Vec::with_capacity(role_args.len());
+        for role_id_or_name in role_args {
+            let role_id = self
+                .assignable_role(&server, &message.author_id, role_id_or_name)
+                .await?;
+            roles.push(role_id);
+        }
+
+        let Ok(member) = self
+            .cache
+            .fetch_member(&self.http, &server.id, &user_id)
+            .await
+        else {
+            return Err(Error::Custom("Member not found!".to_string()));
+        };
+        let bot_member = self
+            .cache
+            .fetch_member(&self.http, &server.id, my_id)
+            .await?;
+        let author_member = self
+            .cache
+            .fetch_member(&self.http, &server.id, &message.author_id)
+            .await?;
+        if bot_member.rank(&server) >= member.rank(&server) {
+            return Err(Error::MemberRankTooHigh);
+        }
+        if author_member.effective_rank(&server) >= member.effective_rank(&server) {
+            return Err(Error::MemberRankAboveUser);
+        }
+
+        if let Some(expires) = expires {
+            let due = unix_time() + expires.as_secs() as i64;
+            for role_id in &roles {
+                self.db
+                    .schedule_expiry(&server.id, &user_id, role_id, due)
+                    .await?;
+            }
+        }
+        let action = if give {
+            RoleAction {
+                give: roles,
+                remove: Vec::new(),
+                limit: None,
+            }
+        } else {
+            RoleAction {
+                give: Vec::new(),
+                remove: roles,
+                limit: None,
+            }
+        };
+        println!(
+            "Manual: Server: {}, Member: {user_id}, By: {}, Give: {:?}, Take: {:?}",
+            &server.id, &message.author_id, &action.give, &action.remove
+        );
+        self.queue_edit(&server.id, user_id, action).await;
+
+        let send = match expires {
+            Some(expires) => format!("Roles given for {}!", format_duration(expires)),
+            None if give => "Roles given!".to_string(),
+            None => "Roles taken!".to_string(),
+        };
+        self.http.send_message(&message.channel_id, send).await?;
+        Ok(())
+    }
+}
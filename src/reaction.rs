@@ -1,25 +1,129 @@
 use std::{
     collections::{HashMap, HashSet},
+    fmt::Write,
     ops::Range,
+    sync::{Arc, atomic::Ordering},
 };
 
+use emojis::SkinTone;
 use indexmap::IndexMap;
 use once_cell::sync::Lazy;
-use regex::Regex;
+use regex::{Captures, Regex};
 use tokio::{
-    sync::mpsc::{Receiver, Sender, channel},
-    time::sleep,
+    sync::{
+        mpsc::{Receiver, Sender, channel},
+        watch,
+    },
+    task::JoinHandle,
+    time::{Duration, Instant, sleep, sleep_until, timeout},
 };
 use volty::{http::routes::servers::member_edit::MemberEdit, prelude::*};
 
-use crate::{Bot, error::Error};
+use crate::{
+    Bot, constants::MAX_ROLE_EXPIRY, database::SqliteDB, error::Error, format_duration,
+    parse_duration, scheduler::unix_time,
+};
+
+// Unreact events for reactions the bot removed are ignored for this long.
+const REMOVED_REACTION_TTL: Duration = Duration::from_secs(60);
+
+/// An emoji in role message content, `:shortcode:`, `:CUSTOM_EMOJI_ID:` or unicode.
+/// Unicode can be a skin tone or ZWJ sequence with variation selectors.
+pub const EMOJI_PATTERN: &str = r"(:[a-zA-Z0-9_+-]+:|\p{Extended_Pictographic}[\p{Emoji_Modifier}\x{FE0F}]*(?:\x{200D}\p{Extended_Pictographic}[\p{Emoji_Modifier}\x{FE0F}]*)*)";
+
+/// Key used for an emoji in role messages, unicode emojis are stored by shortcode.
+/// Custom emojis are kept by ID, skin tones and unicode without a shortcode as unicode.
+pub fn emoji_key(emoji: &str) -> &str {
+    let emoji = emoji.trim_matches(':');
+    let found = emojis::get(emoji)
+        .or_else(|| emojis::get(emoji.trim_end_matches('\u{fe0f}')))
+        .or_else(|| emojis::get(&format!("{emoji}\u{fe0f}")))
+        .or_else(|| emojis::get_by_shortcode(emoji));
+    match found {
+        // Skin tones share their base emoji's shortcode.
+        Some(e) if e.skin_tone().is_some_and(|t| t != SkinTone::Default) => e.as_str(),
+        Some(e) => e.shortcode().unwrap_or(e.as_str()),
+        None => emoji,
+    }
+}
+
+/// Emoji as members react with it, from unicode, `:shortcode:` or a custom emoji ID.
+/// Unicode is kept with its skin tone.
+pub fn reaction_emoji(emoji: &str) -> &str {
+    let emoji = emoji.trim().trim_matches(':');
+    emojis::get_by_shortcode(emoji).map_or(emoji, |e| e.as_str())
+}
+
+/// How an emoji key is written in role message content.
+pub fn emoji_text(key: &str) -> String {
+    if key.is_ascii() {
+        format!(":{key}:")
+    } else {
+        key.to_string()
+    }
+}
+
+/// Group names are lowercase letters, numbers, `_` and `-`.
+pub fn valid_group(name: &str) -> Option<String> {
+    let is_valid = (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    is_valid.then(|| name.to_lowercase())
+}
+
+/// Role name or ID in setup syntax, IDs can be written as `<%ROLE_ID>`.
+pub fn role_name_or_id(role: &str) -> String {
+    let role = role.trim();
+    role.strip_prefix("<%")
+        .and_then(|r| r.strip_suffix('>'))
+        .unwrap_or(role)
+        .to_string()
+}
+
+/// Options set by the keywords before a setup message.
+#[derive(Clone, Debug, Default)]
+pub struct SetupOptions {
+    pub exclusive: bool,
+    pub formatted: bool,
+    pub verify: bool,
+    pub reverse: bool,
+    pub expires: Option<Duration>,
+    // Role name or ID members need before reacting.
+    pub requires: Option<String>,
+    pub limit: Option<usize>,
+    pub server_group: Option<String>,
+}
+
+impl SetupOptions {
+    /// Modes are kept in the message so they survive without the database.
+    fn modes(&self) -> String {
+        let mut modes = String::new();
+        if self.exclusive {
+            modes.push_str("[](EXCLUSIVE)");
+        }
+        if self.verify {
+            modes.push_str("[](VERIFY)");
+        }
+        if self.reverse {
+            modes.push_str("[](REVERSE)");
+        }
+        modes
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct SetupMessage {
     author_id: String,
     content: String,
-    roles: Vec<(Range<usize>, String)>,
+    // Role names or IDs given by each emoji and its exclusive group
+    roles: Vec<(Range<usize>, Vec<String>, Option<String>)>,
     is_formatted: bool,
+    expires: Option<Duration>,
+    // Role name or ID members need before reacting.
+    requires: Option<String>,
+    limit: Option<usize>,
+    server_group: Option<String>,
 }
 
 impl SetupMessage {
@@ -27,48 +131,167 @@ impl SetupMessage {
         &self.content
     }
 
+    pub fn expires(&self) -> Option<Duration> {
+        self.expires
+    }
+
+    pub fn requires(&self) -> Option<&str> {
+        self.requires.as_deref()
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn server_group(&self) -> Option<&str> {
+        self.server_group.as_deref()
+    }
+
     pub fn parse(author_id: String, mut content: &str) -> Option<Self> {
+        fn next_word(content: &str) -> (&str, &str) {
+            let (word, rest) = content
+                .split_once(char::is_whitespace)
+                .unwrap_or((content, ""));
+            (word, rest.trim_start())
+        }
+        fn names_or_ids(capture: &Captures) -> Vec<String> {
+            capture[1].split(',').map(role_name_or_id).collect()
+        }
+
+        // `{ROLE:Name}` or `{ROLE:Name,<%ROLE_ID>}` for several roles on one emoji.
+        // `{ROLE:Name|group}` makes the emoji exclusive with others in the group.
+        static RE: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"(?i)\{ROLE:([^{},|]{1,32}(?:,[^{},|]{1,32})*)(?:\|([a-z0-9_-]{1,32}))?}")
+                .unwrap()
+        });
+
         content = content.trim();
-        let mut is_exclusive = false;
-        let mut is_formatted = false;
-        for word in content.split_whitespace() {
+        let mut options = SetupOptions::default();
+        loop {
+            let (word, rest) = next_word(content);
             if word.eq_ignore_ascii_case("exclusive") {
-                content = content["exclusive".len()..].trim_start();
-                is_exclusive = true;
+                options.exclusive = true;
             } else if word.eq_ignore_ascii_case("formatted") {
-                content = content["formatted".len()..].trim_start();
-                is_formatted = true;
+                options.formatted = true;
+            } else if word.eq_ignore_ascii_case("verify") {
+                options.verify = true;
+            } else if word.eq_ignore_ascii_case("reverse") {
+                options.reverse = true;
+            } else if word.eq_ignore_ascii_case("expires") {
+                let (duration, rest) = next_word(rest);
+                let Some(duration) = parse_duration(duration) else {
+                    break;
+                };
+                options.expires = Some(duration);
+                content = rest;
+                continue;
+            } else if word.eq_ignore_ascii_case("limit") {
+                let (max, rest) = next_word(rest);
+                let Some(max) = max.parse::<usize>().ok().filter(|&max| max > 0) else {
+                    break;
+                };
+                options.limit = Some(max);
+                content = rest;
+                continue;
+            } else if word.eq_ignore_ascii_case("group") {
+                let (name, rest) = next_word(rest);
+                let Some(name) = valid_group(name) else {
+                    break;
+                };
+                options.server_group = Some(name);
+                content = rest;
+                continue;
+            } else if word.eq_ignore_ascii_case("requires") {
+                let Some(capture) = RE.captures(rest).filter(|c| c.get(0).unwrap().start() == 0)
+                else {
+                    break;
+                };
+                let [role] = &names_or_ids(&capture)[..] else {
+                    break;
+                };
+                options.requires = Some(role.clone());
+                content = rest[capture.get(0).unwrap().end()..].trim_start();
+                continue;
             } else {
                 break;
             }
+            content = rest;
         }
-        let content = if is_exclusive {
-            format!("[](EXCLUSIVE){content}")
-        } else {
-            content.to_string()
-        };
-        static RE: Lazy<Regex> = Lazy::new(|| {
-            Regex::new(r"(?i)\{ROLE:(?:<%([0-9A-HJKMNP-TV-Z]{26})>|([^{}]{1,32}))}").unwrap()
-        });
+        let content = format!("{}{content}", options.modes());
         let captures = RE.captures_iter(&content);
         let mut roles = Vec::new();
         for capture in captures {
             let range = capture.get(0).unwrap().range();
-            let name_or_id = capture
-                .get(1)
-                .unwrap_or_else(|| capture.get(2).unwrap())
-                .as_str();
-            roles.push((range, name_or_id.into()));
+            let group = capture.get(2).map(|g| g.as_str().to_lowercase());
+            roles.push((range, names_or_ids(&capture), group));
         }
         if roles.is_empty() {
             return None;
         }
-        Some(Self {
+        Some(Self::new(author_id, content, roles, options))
+    }
+
+    /// Setup message from parts instead of setup syntax, no text is parsed for keywords or roles.
+    /// `roles` are comma separated role names or IDs with their group and text, one per line.
+    pub fn from_parts(
+        author_id: String,
+        options: SetupOptions,
+        text: &str,
+        roles: &[(&str, Option<&str>, &str)],
+    ) -> Self {
+        let mut content = format!("{}{text}", options.modes());
+        let mut ranges = Vec::with_capacity(roles.len());
+        for &(names_or_ids, group, role_text) in roles {
+            content.push('\n');
+            let start = content.len();
+            write!(content, "{{ROLE:{names_or_ids}}}").unwrap();
+            let names_or_ids = names_or_ids.split(',').map(role_name_or_id).collect();
+            let group = group.map(str::to_lowercase);
+            ranges.push((start..content.len(), names_or_ids, group));
+            write!(content, " {role_text}").unwrap();
+        }
+        Self::new(author_id, content, ranges, options)
+    }
+
+    fn new(
+        author_id: String,
+        content: String,
+        roles: Vec<(Range<usize>, Vec<String>, Option<String>)>,
+        options: SetupOptions,
+    ) -> Self {
+        Self {
             author_id,
             content,
             roles,
-            is_formatted,
-        })
+            is_formatted: options.formatted,
+            expires: options.expires,
+            requires: options.requires,
+            limit: options.limit,
+            server_group: options.server_group,
+        }
+    }
+
+    /// Parses the finished content, options not kept in the content are copied over.
+    pub fn role_message(
+        &self,
+        channel_id: String,
+        server: &Server,
+        content: &str,
+    ) -> Result<Option<RoleMessage>, Error> {
+        let Some(mut role_message) = RoleMessage::parse(channel_id, server.id.clone(), content)
+        else {
+            return Ok(None);
+        };
+        role_message.expires = self.expires;
+        role_message.limit = self.limit;
+        role_message.server_group = self.server_group.clone();
+        if let Some(name_or_id) = &self.requires {
+            let Some((role_id, _role)) = server.role_by_id_or_name(name_or_id) else {
+                return Err(Error::InvalidRole(name_or_id.clone()));
+            };
+            role_message.requires = Some(role_id.to_string());
+        }
+        Ok(Some(role_message))
     }
 
     pub fn with_emojis(&self, emojis: &[&str], server: &Server) -> Option<String> {
@@ -94,14 +317,23 @@ impl SetupMessage {
             }
             if i == role.0.start {
                 let emoji = emojis[role_index];
-                let emoji = emojis::get(emoji)
-                    .and_then(emojis::Emoji::shortcode)
-                    .unwrap_or(emoji);
-                let (role_id, role) = server.role_by_id_or_name(&role.1)?;
+                let emoji = emoji_text(emoji_key(emoji));
+                let mut role_ids = Vec::with_capacity(role.1.len());
+                let mut names = Vec::with_capacity(role.1.len());
+                for name_or_id in &role.1 {
+                    let (role_id, role) = server.role_by_id_or_name(name_or_id)?;
+                    role_ids.push(role_id);
+                    names.push(role.name.as_str());
+                }
+                let mut role_ids = role_ids.join(",");
+                if let Some(group) = &role.2 {
+                    role_ids = format!("{role_ids}#{group}");
+                }
                 if self.is_formatted {
-                    with_emojis.push_str(&format!(":{emoji}:[]({role_id})"));
+                    with_emojis.push_str(&format!("{emoji}[]({role_ids})"));
                 } else {
-                    with_emojis.push_str(&format!(":{emoji}:[]({role_id}) __{}__", role.name));
+                    let names = names.join(", ");
+                    with_emojis.push_str(&format!("{emoji}[]({role_ids}) __{names}__"));
                 }
                 continue;
             }
@@ -112,28 +344,118 @@ impl SetupMessage {
 
 #[derive(Clone, Debug)]
 pub struct RoleMessage {
-    exclusive: bool,
-    // k=Emoji, v=RoleID
-    roles: HashMap<String, String>,
+    pub channel_id: String,
+    pub server_id: String,
+    pub exclusive: bool,
+    // Unreacting doesn't remove the role.
+    pub verify: bool,
+    // Reacting removes the role and unreacting gives it back.
+    pub reverse: bool,
+    // RoleID members need before they can be given roles.
+    pub requires: Option<String>,
+    // Max roles from this message a member can have.
+    pub limit: Option<usize>,
+    // Roles given by reacting are removed after this long.
+    pub expires: Option<Duration>,
+    // k=Emoji, v=RoleIDs
+    pub roles: HashMap<String, Vec<String>>,
+    // k=Emoji, v=Group, emojis in the same group are exclusive.
+    pub groups: HashMap<String, String>,
+    // Server wide group, roles are exclusive with every message in the group.
+    pub server_group: Option<String>,
 }
 
 impl RoleMessage {
-    fn parse(content: &str) -> Option<Self> {
+    fn parse(channel_id: String, server_id: String, content: &str) -> Option<Self> {
         static RE: Lazy<Regex> = Lazy::new(|| {
-            Regex::new(r"(?i):([a-z0-9_-]+):\[]\(([0-9A-HJKMNP-TV-Z]{26})\)").unwrap()
+            Regex::new(&format!(
+                r"(?i){EMOJI_PATTERN}\[]\(([0-9A-HJKMNP-TV-Z]{{26}}(?:,[0-9A-HJKMNP-TV-Z]{{26}})*)(?:#([a-z0-9_-]+))?\)"
+            ))
+            .unwrap()
         });
         let captures = RE.captures_iter(content);
         let mut roles = HashMap::new();
+        let mut groups = HashMap::new();
         for capture in captures {
-            let emoji = capture.get(1).unwrap().as_str();
-            let role_id = capture.get(2).unwrap().as_str();
-            roles.insert(emoji.to_string(), role_id.to_string());
+            let emoji = emoji_key(capture.get(1).unwrap().as_str());
+            let role_ids = capture.get(2).unwrap().as_str();
+            let role_ids = role_ids.split(',').map(String::from).collect();
+            roles.insert(emoji.to_string(), role_ids);
+            if let Some(group) = capture.get(3) {
+                groups.insert(emoji.to_string(), group.as_str().to_string());
+            }
         }
         if roles.is_empty() {
             return None;
         }
-        let exclusive = content.starts_with("[](EXCLUSIVE)");
-        Some(Self { exclusive, roles })
+        let mut exclusive = false;
+        let mut verify = false;
+        let mut reverse = false;
+        let mut modes = content;
+        loop {
+            if let Some(rest) = modes.strip_prefix("[](EXCLUSIVE)") {
+                exclusive = true;
+                modes = rest;
+            } else if let Some(rest) = modes.strip_prefix("[](VERIFY)") {
+                verify = true;
+                modes = rest;
+            } else if let Some(rest) = modes.strip_prefix("[](REVERSE)") {
+                reverse = true;
+                modes = rest;
+            } else {
+                break;
+            }
+        }
+        Some(Self {
+            channel_id,
+            server_id,
+            exclusive,
+            verify,
+            reverse,
+            expires: None,
+            requires: None,
+            limit: None,
+            roles,
+            groups,
+            server_group: None,
+        })
+    }
+
+    /// Other emojis swapped out when reacting to `emoji`, from exclusive mode or a group.
+    pub fn exclusive_emojis(&self, emoji: &str) -> Vec<&str> {
+        let group = self.groups.get(emoji);
+        let is_exclusive = self.exclusive || self.server_group.is_some();
+        if !is_exclusive && group.is_none() {
+            return Vec::new();
+        }
+        self.roles
+            .keys()
+            .filter(|&e| e != emoji && (is_exclusive || self.groups.get(e) == group))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Roles taken away when reacting to `emoji`, from exclusive mode or a group.
+    pub fn exclusive_roles(&self, emoji: &str) -> Vec<String> {
+        let role_ids = &self.roles[emoji];
+        self.exclusive_emojis(emoji)
+            .into_iter()
+            .flat_map(|e| &self.roles[e])
+            .filter(|&r| !role_ids.contains(r))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether reacting to all of `emojis` breaks exclusive mode or a group.
+    pub fn is_conflict(&self, emojis: &[&str]) -> bool {
+        if self.exclusive {
+            return emojis.len() > 1;
+        }
+        let mut groups = HashSet::new();
+        emojis
+            .iter()
+            .filter_map(|&e| self.groups.get(e))
+            .any(|g| !groups.insert(g))
     }
 }
 
@@ -146,76 +468,277 @@ pub enum RoleReact {
 pub struct RoleAction {
     pub give: Vec<String>,
     pub remove: Vec<String>,
+    // Checked against the member's roles when edits are merged.
+    pub limit: Option<RoleLimit>,
+}
+
+/// At most `max` of `roles` can be held, roles over the limit aren't given.
+#[derive(Clone)]
+pub struct RoleLimit {
+    pub roles: Vec<String>,
+    pub max: usize,
 }
 
-pub type ServerSender = Sender<(String, RoleAction)>;
-pub type ServerReceiver = Receiver<(String, RoleAction)>;
+impl RoleLimit {
+    /// Whether all of `give` fit under the limit with the roles already held.
+    pub fn allows(&self, roles: &HashSet<String>, give: &[String]) -> bool {
+        let held = self.roles.iter().filter(|&r| roles.contains(r)).count();
+        let new = give
+            .iter()
+            .filter(|&r| self.roles.contains(r) && !roles.contains(r))
+            .count();
+        new == 0 || held + new <= self.max
+    }
+}
+
+// Database ID of the queued edit, None if it couldn't be saved.
+pub type QueuedEdit = (Option<i64>, String, RoleAction);
+pub type ServerSender = Sender<QueuedEdit>;
+pub type ServerReceiver = Receiver<QueuedEdit>;
+
+pub struct ServerHandle {
+    tx: ServerSender,
+    task: JoinHandle<()>,
+}
 
-async fn server_handler(server_id: String, http: Http, cache: Cache, mut rx: ServerReceiver) {
-    let mut next: Option<(String, RoleAction)> = None;
-    let mut edits: IndexMap<String, HashSet<String>> = IndexMap::new();
+// Transient failures are retried after this, doubling each attempt.
+const EDIT_RETRY_DELAY: Duration = Duration::from_secs(2);
+// Edits failing this many times are left in the database for the next start.
+const MAX_EDIT_ATTEMPTS: u32 = 6;
+
+struct PendingEdit {
+    // Applied in order to the member's roles when the edit is sent.
+    actions: Vec<RoleAction>,
+    ids: Vec<i64>,
+    attempts: u32,
+    retry_at: Option<Instant>,
+}
+
+async fn server_handler(
+    server_id: String,
+    http: Http,
+    cache: Cache,
+    db: Arc<SqliteDB>,
+    mut rx: ServerReceiver,
+    mut stop: watch::Receiver<bool>,
+) {
+    let mut next: Option<QueuedEdit> = None;
+    let mut edits: IndexMap<String, PendingEdit> = IndexMap::new();
+    let mut closed = false;
     'outer: loop {
-        while let Some((user_id, action)) = next {
-            if !edits.contains_key(&user_id) {
-                let Ok(member) = cache.fetch_member(&http, &server_id, &user_id).await else {
-                    next = rx.try_recv().ok();
-                    continue;
-                };
-                edits.insert(user_id.clone(), member.roles);
-            }
-            let edit = edits.get_mut(&user_id).unwrap();
-            edit.extend(action.give);
-            for role in action.remove {
-                edit.remove(&role);
-            }
+        while let Some((id, user_id, action)) = next {
+            let edit = edits.entry(user_id).or_insert_with(|| PendingEdit {
+                actions: Vec::new(),
+                ids: Vec::new(),
+                attempts: 0,
+                retry_at: None,
+            });
+            edit.actions.push(action);
+            edit.ids.extend(id);
             next = rx.try_recv().ok();
         }
 
-        for (user_id, roles) in &edits {
-            let Ok(member) = cache.fetch_member(&http, &server_id, user_id).await else {
-                continue;
-            };
-            if *roles == member.roles {
-                continue;
-            };
-            let giving = roles.difference(&member.roles);
-            let taking = member.roles.difference(roles);
-            println!(
-                "Server: {server_id}, Member: {user_id}\n\tGiving: {giving:?}\n\tTaking: {taking:?}"
-            );
-            let data = MemberEdit::new().roles(roles);
-            let result = http.edit_member(&server_id, user_id, data).await;
+        let now = Instant::now();
+        let ready: Vec<String> = edits
+            .iter()
+            .filter(|(_, edit)| edit.retry_at.is_none_or(|at| at <= now))
+            .map(|(user_id, _)| user_id.clone())
+            .collect();
+        for user_id in ready {
+            if *stop.borrow() {
+                defer_pending(&server_id, &edits, &mut rx);
+                return;
+            }
+            let actions = &edits[&user_id].actions;
+            let result = apply_edit(&server_id, &http, &cache, &user_id, actions).await;
             match result {
                 Err(HttpError::Api(ApiError::RetryAfter(duration))) => {
                     println!("RetryAfter: {duration:?}");
-                    sleep(duration).await;
-                    if let Some(index) = edits.get_index_of(user_id)
-                        && index > 0
-                    {
-                        edits.drain(0..index);
+                    tokio::select! {
+                        _ = sleep(duration) => {}
+                        Ok(_) = stop.wait_for(|&stop| stop) => {
+                            defer_pending(&server_id, &edits, &mut rx);
+                            return;
+                        }
                     }
                     next = rx.try_recv().ok();
                     continue 'outer;
                 }
+                Err(e) if !is_permanent(&e) => {
+                    dbg!(e);
+                    let edit = edits.get_mut(&user_id).unwrap();
+                    edit.attempts += 1;
+                    if edit.attempts < MAX_EDIT_ATTEMPTS {
+                        let delay = EDIT_RETRY_DELAY * 2u32.pow(edit.attempts - 1);
+                        edit.retry_at = Some(Instant::now() + delay);
+                    } else {
+                        // Replayed on the next start unless a later edit supersedes it.
+                        let edit = edits.shift_remove(&user_id).unwrap();
+                        log_pending(&server_id, &user_id, &edit);
+                    }
+                    continue;
+                }
                 Err(e) => {
                     dbg!(e);
                 }
-                _ => {}
+                Ok(()) => {}
             }
+            let edit = edits.shift_remove(&user_id).unwrap();
+            finish_edits(&db, &server_id, &user_id, &edit.ids).await;
         }
-        edits.clear();
-        next = rx.recv().await;
-        if next.is_none() {
-            return;
+
+        let Some(retry_at) = edits.values().filter_map(|edit| edit.retry_at).min() else {
+            next = rx.recv().await;
+            if next.is_none() {
+                return;
+            }
+            continue;
+        };
+        tokio::select! {
+            edit = rx.recv(), if !closed => {
+                next = edit;
+                closed = next.is_none();
+            }
+            _ = sleep_until(retry_at) => {}
+            Ok(_) = stop.wait_for(|&stop| stop) => {
+                defer_pending(&server_id, &edits, &mut rx);
+                return;
+            }
         }
     }
 }
 
+/// Applies `actions` in order to the member's current roles and saves the result.
+async fn apply_edit(
+    server_id: &str,
+    http: &Http,
+    cache: &Cache,
+    user_id: &str,
+    actions: &[RoleAction],
+) -> Result<(), HttpError> {
+    let member = cache.fetch_member(http, server_id, user_id).await?;
+    let mut roles = member.roles.clone();
+    for action in actions {
+        // Removals first so they free up room under the limit.
+        // Actions are applied whole, a role set is never given in part.
+        let mut next = roles.clone();
+        for role in &action.remove {
+            next.remove(role);
+        }
+        if action
+            .limit
+            .as_ref()
+            .is_some_and(|limit| !limit.allows(&next, &action.give))
+        {
+            println!(
+                "Server: {server_id}, Member: {user_id}, over limit: {:?}",
+                &action.give
+            );
+            continue;
+        }
+        next.extend(action.give.iter().cloned());
+        roles = next;
+    }
+    if roles == member.roles {
+        return Ok(());
+    }
+    let giving = roles.difference(&member.roles);
+    let taking = member.roles.difference(&roles);
+    println!("Server: {server_id}, Member: {user_id}\n\tGiving: {giving:?}\n\tTaking: {taking:?}");
+    let data = MemberEdit::new().roles(&roles);
+    http.edit_member(server_id, user_id, data).await?;
+    Ok(())
+}
+
+/// Errors retrying won't fix, the member or server is gone or the bot lacks permissions.
+pub fn is_permanent(error: &HttpError) -> bool {
+    matches!(
+        error,
+        HttpError::Api(ApiError::NotFound | ApiError::MissingPermission { .. })
+    )
+}
+
+/// Finishes `ids` and any older edit of the member left by a failed run, this one replaces them.
+async fn finish_edits(db: &SqliteDB, server_id: &str, user_id: &str, ids: &[i64]) {
+    let Some(&last_id) = ids.iter().max() else {
+        return;
+    };
+    if let Err(e) = db.finish_edits(server_id, user_id, last_id).await {
+        dbg!(e);
+    }
+}
+
+fn log_unsent(server_id: &str, (id, user_id, action): &QueuedEdit) {
+    let status = if id.is_some() { "Deferred" } else { "Dropped" };
+    println!(
+        "{status}: Server: {server_id}, Member: {user_id}, Give: {:?}, Take: {:?}",
+        &action.give, &action.remove
+    );
+}
+
+fn log_pending(server_id: &str, user_id: &str, edit: &PendingEdit) {
+    let status = if edit.ids.is_empty() {
+        "Dropped"
+    } else {
+        "Deferred"
+    };
+    for action in &edit.actions {
+        println!(
+            "{status}: Server: {server_id}, Member: {user_id}, Give: {:?}, Take: {:?}",
+            &action.give, &action.remove
+        );
+    }
+}
+
+// Edits left in the database are replayed on the next start.
+fn defer_pending(server_id: &str, edits: &IndexMap<String, PendingEdit>, rx: &mut ServerReceiver) {
+    for (user_id, edit) in edits {
+        log_pending(server_id, user_id, edit);
+    }
+    while let Ok(edit) = rx.try_recv() {
+        log_unsent(server_id, &edit);
+    }
+}
+
 impl Bot {
     pub async fn queue_edit(&self, server_id: &str, user_id: String, action: RoleAction) {
+        let id = match self.db.save_edit(server_id, &user_id, &action).await {
+            Ok(id) => Some(id),
+            Err(e) => {
+                dbg!(e);
+                None
+            }
+        };
+        self.send_edit(server_id, (id, user_id, action)).await;
+    }
+
+    /// Queues edits left in the database by a previous run.
+    pub async fn replay_edits(&self) {
+        let edits = match self.db.get_pending_edits().await {
+            Ok(edits) => edits,
+            Err(e) => {
+                dbg!(e);
+                return;
+            }
+        };
+        if edits.is_empty() {
+            return;
+        }
+        println!("Replaying {} queued role edits", edits.len());
+        for (id, server_id, user_id, action) in edits {
+            self.send_edit(&server_id, (Some(id), user_id, action))
+                .await;
+        }
+    }
+
+    async fn send_edit(&self, server_id: &str, edit: QueuedEdit) {
+        if self.closing.load(Ordering::SeqCst) {
+            log_unsent(server_id, &edit);
+            return;
+        }
         let handlers = self.server_handlers.read().await;
-        if let Some(sender) = handlers.get(server_id) {
-            match sender.send((user_id.clone(), action.clone())).await {
+        if let Some(handle) = handlers.get(server_id) {
+            match handle.tx.send(edit.clone()).await {
                 Ok(_) => return,
                 Err(e) => {
                     dbg!(e);
@@ -223,49 +746,138 @@ impl Bot {
             };
         }
         drop(handlers);
+
+        let mut handlers = self.server_handlers.write().await;
+        if self.closing.load(Ordering::SeqCst) {
+            log_unsent(server_id, &edit);
+            return;
+        }
         let cache = self.cache.clone();
         let http = self.http.clone();
+        let db = self.db.clone();
         let (tx, rx) = channel(100);
-        let server_id = server_id.to_string();
-        let server_id_ = server_id.clone();
-        tokio::spawn(server_handler(server_id, http, cache, rx));
+        let stop = self.stop.subscribe();
+        let task = tokio::spawn(server_handler(
+            server_id.to_string(),
+            http,
+            cache,
+            db,
+            rx,
+            stop,
+        ));
 
-        if let Err(e) = tx.send((user_id, action)).await {
+        if let Err(e) = tx.send(edit).await {
             dbg!(e);
         }
-        let mut handlers = self.server_handlers.write().await;
-        handlers.insert(server_id_, tx);
+        handlers.insert(server_id.to_string(), ServerHandle { tx, task });
+    }
+
+    /// Stops accepting role edits and waits for every server queue to flush.
+    /// Edits still queued when the deadline passes are left for the next start.
+    pub async fn shutdown(&self, deadline: Duration) {
+        self.closing.store(true, Ordering::SeqCst);
+        let handlers: Vec<_> = self.server_handlers.write().await.drain().collect();
+        println!("Shutdown: flushing {} server queues", handlers.len());
+
+        let mut tasks = Vec::with_capacity(handlers.len());
+        for (server_id, handle) in handlers {
+            drop(handle.tx);
+            tasks.push((server_id, handle.task));
+        }
+        let flush = async {
+            for (_, task) in &mut tasks {
+                let _ = task.await;
+            }
+        };
+        if timeout(deadline, flush).await.is_ok() {
+            println!("Shutdown: all server queues flushed");
+            return;
+        }
+
+        self.stop.send_replace(true);
+        for (server_id, task) in &mut tasks {
+            if task.is_finished() {
+                continue;
+            }
+            println!("Shutdown: deadline passed for Server: {server_id}");
+            if timeout(Duration::from_secs(5), &mut *task).await.is_err() {
+                task.abort();
+            }
+        }
     }
 
-    async fn role_message(
+    pub async fn role_message(
         &self,
         channel_id: &str,
         message_id: &str,
-    ) -> Result<Option<RoleMessage>, HttpError> {
+    ) -> Result<Option<RoleMessage>, Error> {
         if let Some(role_message) = self.role_messages.read().await.get(message_id) {
             return Ok(Some(role_message.clone()));
         }
+        if let Some(role_message) = self.db.get_role_message(message_id).await? {
+            self.role_messages
+                .write()
+                .await
+                .insert(message_id.to_string(), role_message.clone());
+            return Ok(Some(role_message));
+        }
+
+        // Messages created before role messages were stored in the database.
+        let Some(server) = self.get_server(channel_id).await else {
+            return Ok(None);
+        };
         let message = self
             .cache
             .fetch_message(&self.http, channel_id, message_id)
             .await?;
-        let role_message = message.content.as_ref().and_then(|c| RoleMessage::parse(c));
-        if let Some(message) = role_message.as_ref() {
+        let role_message = message
+            .content
+            .as_ref()
+            .and_then(|c| RoleMessage::parse(channel_id.to_string(), server.id, c));
+        if let Some(role_message) = role_message.as_ref() {
+            self.db.save_role_message(message_id, role_message).await?;
             self.role_messages
                 .write()
                 .await
-                .insert(message_id.to_string(), message.clone());
+                .insert(message_id.to_string(), role_message.clone());
         }
         Ok(role_message)
     }
 
-    async fn check_role_message(
+    /// Finds a role message in this server, `fallback_channel_id` is used for unlinked
+    /// messages that aren't stored yet. Returns the channel ID and role message.
+    pub async fn find_role_message(
+        &self,
+        server_id: &str,
+        channel_id: Option<&str>,
+        message_id: &str,
+        fallback_channel_id: &str,
+    ) -> Result<(String, RoleMessage), Error> {
+        let channel_id = match channel_id {
+            Some(channel_id) => channel_id.to_string(),
+            None => match self.db.get_role_message(message_id).await? {
+                Some(role_message) => role_message.channel_id,
+                None => fallback_channel_id.to_string(),
+            },
+        };
+        match self.role_message(&channel_id, message_id).await {
+            Ok(Some(role_message)) if role_message.server_id == server_id => {
+                Ok((channel_id, role_message))
+            }
+            Ok(_) | Err(Error::Http(_)) => {
+                Err(Error::Custom("Role message not found!".to_string()))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn check_role_message(
         &self,
         server_id: &str,
         author_id: &str,
         role_message: &RoleMessage,
     ) -> Result<(), Error> {
-        let role_ids = role_message.roles.values().map(String::as_str);
+        let role_ids = role_message.roles.values().flatten().map(String::as_str);
         self.check_above_roles(server_id, self.cache.user_id(), role_ids.clone())
             .await?;
         self.check_above_roles(server_id, author_id, role_ids)
@@ -319,8 +931,18 @@ impl Bot {
             .await?;
         self.check_server_perms(server_id, author_id, &[Permission::AssignRoles])
             .await?;
+        if setup_message.expires.is_some_and(|e| e > MAX_ROLE_EXPIRY) {
+            return Err(Error::Custom(format!(
+                "Expiry must be {} or less!",
+                format_duration(MAX_ROLE_EXPIRY)
+            )));
+        }
 
-        let ids_or_names = setup_message.roles.iter().map(|(_, i)| i.as_str());
+        let ids_or_names = setup_message
+            .roles
+            .iter()
+            .flat_map(|(_, r)| r)
+            .map(String::as_str);
         self.check_above_roles(server_id, self.cache.user_id(), ids_or_names.clone())
             .await?;
         self.check_above_roles(server_id, author_id, ids_or_names)
@@ -348,8 +970,39 @@ impl Bot {
             return Ok(());
         };
         if interactions.restrict_reactions {
-            self.on_role_react(channel_id, message_id, user_id, emoji_id, action)
-                .await?;
+            let is_react = matches!(action, RoleReact::React);
+            // Kept in sync so reconciling only acts on reactions changed while offline.
+            let emoji = emoji_key(emoji_id);
+            let saved = if is_react {
+                self.db.add_reactor(message_id, emoji, user_id).await
+            } else {
+                self.db.remove_reactor(message_id, emoji, user_id).await
+            };
+            if let Err(e) = saved {
+                dbg!(e);
+            }
+            let result = self
+                .on_role_react(channel_id, message_id, user_id, emoji_id, action)
+                .await;
+            // Refused reactions are taken off so the message matches the member's roles.
+            let is_refused = matches!(
+                &result,
+                Err(Error::InvalidRole(_)
+                    | Error::Missing(_)
+                    | Error::MemberRankTooHigh
+                    | Error::RoleRankTooHigh(_)
+                    | Error::RequiredRoleMissing(_)
+                    | Error::RoleLimitReached(_))
+            );
+            if is_react && is_refused {
+                let removed = self
+                    .remove_reaction(channel_id, message_id, user_id, emoji_id)
+                    .await;
+                if let Err(e) = removed {
+                    dbg!(e);
+                }
+            }
+            result?;
         } else if message.replies.is_some() {
             self.on_setup_react(message, user_id).await?;
         }
@@ -371,7 +1024,13 @@ impl Bot {
             Error::RoleRankTooHigh(role) => {
                 format!("I can only assign roles below my own!\n{role}")
             }
-            Error::UserMissing(_) | Error::UserRankTooLow(_) => {
+            Error::RequiredRoleMissing(role) => {
+                format!("You need the `{role}` role to use this message!")
+            }
+            Error::RoleLimitReached(max) => {
+                format!("You can only have {max} roles from this message!")
+            }
+            Error::UserMissing(_) | Error::UserRankTooLow(_) | Error::MemberRankAboveUser => {
                 unreachable!()
             }
             Error::InvalidUser | Error::Http(_) => return,
@@ -395,19 +1054,29 @@ impl Bot {
         emoji_id: &str,
         action: RoleReact,
     ) -> Result<(), Error> {
-        let emoji_id = emojis::get(emoji_id)
-            .and_then(emojis::Emoji::shortcode)
-            .unwrap_or(emoji_id);
+        let emoji_id = emoji_key(emoji_id);
+        // Hides one unreact after the bot removes a reaction, reacting again or the TTL clears it
+        // in case the unreact event was missed.
+        let key = (
+            message_id.to_string(),
+            user_id.to_string(),
+            emoji_id.to_string(),
+        );
+        let removed_at = self.removed_reactions.write().await.remove(&key);
+        let is_removed = removed_at.is_some_and(|at| at.elapsed() < REMOVED_REACTION_TTL);
+        if matches!(action, RoleReact::Unreact) && is_removed {
+            return Ok(());
+        }
         let Some(role_message) = self.role_message(channel_id, message_id).await? else {
             return Ok(());
         };
-        let Some(role_id) = role_message.roles.get(emoji_id) else {
+        let Some(role_ids) = role_message.roles.get(emoji_id) else {
             return Ok(());
         };
         let Some(server) = self.get_server(channel_id).await else {
             return Ok(());
         };
-        if !server.roles.contains_key(role_id) {
+        if let Some(role_id) = role_ids.iter().find(|&r| !server.roles.contains_key(r)) {
             return Err(Error::InvalidRole(role_id.clone()));
         }
         self.check_server_perms(&server.id, self.cache.user_id(), &[Permission::AssignRoles])
@@ -423,45 +1092,196 @@ impl Bot {
             .await?;
         let bot_rank = bot_member.rank(&server);
         let user_rank = user_member.rank(&server);
-        let role = server.roles.get(role_id).unwrap();
         if bot_rank >= user_rank {
             return Err(Error::MemberRankTooHigh);
         }
-        if bot_rank >= role.rank {
-            return Err(Error::RoleRankTooHigh(role.name.clone()));
+        for role_id in role_ids {
+            let role = server.roles.get(role_id).unwrap();
+            if bot_rank >= role.rank {
+                return Err(Error::RoleRankTooHigh(role.name.clone()));
+            }
         }
 
-        let action = match action {
+        let mut action = match action {
+            RoleReact::Unreact if role_message.verify => return Ok(()),
+            RoleReact::React if role_message.reverse => RoleAction {
+                give: Vec::new(),
+                remove: role_ids.clone(),
+                limit: None,
+            },
+            RoleReact::Unreact if role_message.reverse => RoleAction {
+                give: role_ids.clone(),
+                remove: Vec::new(),
+                limit: None,
+            },
             RoleReact::React => {
-                let remove = if role_message.exclusive {
-                    role_message
-                        .roles
-                        .values()
-                        .filter(|&r| r != role_id)
-                        .cloned()
-                        .collect()
-                } else {
-                    Vec::new()
-                };
+                let mut remove = role_message.exclusive_roles(emoji_id);
+                if let Some(group) = &role_message.server_group {
+                    for role_id in self.db.get_group_roles(&server.id, group).await? {
+                        if !role_ids.contains(&role_id) && !remove.contains(&role_id) {
+                            remove.push(role_id);
+                        }
+                    }
+                }
                 RoleAction {
-                    give: vec![role_id.into()],
+                    give: role_ids.clone(),
                     remove,
+                    limit: None,
                 }
             }
             RoleReact::Unreact => RoleAction {
                 give: Vec::new(),
-                remove: vec![role_id.into()],
+                remove: role_ids.clone(),
+                limit: None,
             },
         };
+        let missing_role = role_message
+            .requires
+            .as_ref()
+            .filter(|r| !action.give.is_empty() && !user_member.roles.contains(*r));
+        if let Some(required_id) = missing_role {
+            let name = server
+                .roles
+                .get(required_id)
+                .map_or(required_id, |r| &r.name);
+            return Err(Error::RequiredRoleMissing(name.clone()));
+        }
+        if let Some(max) = role_message.limit.filter(|_| !action.give.is_empty()) {
+            let mut roles: Vec<String> = role_message.roles.values().flatten().cloned().collect();
+            roles.sort();
+            roles.dedup();
+            let limit = RoleLimit { roles, max };
+            let mut roles = user_member.roles.clone();
+            for role in &action.remove {
+                roles.remove(role);
+            }
+            if !limit.allows(&roles, &action.give) {
+                return Err(Error::RoleLimitReached(max));
+            }
+            action.limit = Some(limit);
+        }
         println!(
             "queue_edit: Server: {}, Member: {}, Give: {:?}, Take: {:?}",
             &server.id, &user_member.id.user, &action.give, &action.remove
         );
-        self.queue_edit(&server.id, user_member.id.user, action)
+        let expires = role_message
+            .expires
+            .filter(|_| !role_message.reverse && !action.give.is_empty());
+        let is_swap = !role_message.reverse && !action.give.is_empty();
+        self.queue_edit(&server.id, user_member.id.user.clone(), action)
             .await;
+        if is_swap {
+            self.remove_swapped_reactions(channel_id, message_id, &role_message, user_id, emoji_id)
+                .await;
+        }
+        if let Some(expires) = expires {
+            let due = unix_time() + expires.as_secs() as i64;
+            for role_id in role_ids {
+                self.db
+                    .schedule_expiry(&server.id, &user_member.id.user, role_id, due)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a member's reaction, the unreact event it causes is ignored.
+    pub async fn remove_reaction(
+        &self,
+        channel_id: &str,
+        message_id: &str,
+        user_id: &str,
+        emoji: &str,
+    ) -> Result<(), Error> {
+        let key = (
+            message_id.to_string(),
+            user_id.to_string(),
+            emoji_key(emoji).to_string(),
+        );
+        let mut removed_reactions = self.removed_reactions.write().await;
+        removed_reactions.retain(|_, at| at.elapsed() < REMOVED_REACTION_TTL);
+        removed_reactions.insert(key.clone(), Instant::now());
+        drop(removed_reactions);
+        if let Err(e) = self
+            .http
+            .unreact_message(channel_id, message_id, emoji, Some(user_id), false)
+            .await
+        {
+            self.removed_reactions.write().await.remove(&key);
+            return Err(e.into());
+        }
         Ok(())
     }
 
+    /// Takes off the member's reactions for roles an exclusive swap removed,
+    /// on this message and on other messages in its server group.
+    async fn remove_swapped_reactions(
+        &self,
+        channel_id: &str,
+        message_id: &str,
+        role_message: &RoleMessage,
+        user_id: &str,
+        emoji_id: &str,
+    ) {
+        let swapped: Vec<String> = role_message
+            .exclusive_emojis(emoji_id)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let mut targets = vec![(channel_id.to_string(), message_id.to_string(), swapped)];
+        if let Some(group) = &role_message.server_group {
+            let group_messages = match self
+                .db
+                .get_group_messages(&role_message.server_id, group)
+                .await
+            {
+                Ok(group_messages) => group_messages,
+                Err(e) => {
+                    dbg!(e);
+                    Vec::new()
+                }
+            };
+            // Every emoji on other messages in the group is swapped out.
+            for (other_id, other_channel_id) in group_messages {
+                if other_id == message_id {
+                    continue;
+                }
+                if let Ok(Some(other)) = self.role_message(&other_channel_id, &other_id).await {
+                    let emojis = other.roles.into_keys().collect();
+                    targets.push((other_channel_id, other_id, emojis));
+                }
+            }
+        }
+
+        for (channel_id, message_id, emojis) in targets {
+            if emojis.is_empty() {
+                continue;
+            }
+            let message = match self
+                .cache
+                .fetch_message(&self.http, &channel_id, &message_id)
+                .await
+            {
+                Ok(message) => message,
+                Err(e) => {
+                    dbg!(e);
+                    continue;
+                }
+            };
+            for (emoji, user_ids) in &message.reactions {
+                if !user_ids.contains(user_id) || !emojis.iter().any(|e| e == emoji_key(emoji)) {
+                    continue;
+                }
+                if let Err(e) = self
+                    .remove_reaction(&channel_id, &message_id, user_id, emoji)
+                    .await
+                {
+                    dbg!(e);
+                }
+            }
+        }
+    }
+
     async fn on_setup_react(&self, message: Message, user_id: &str) -> Result<(), Error> {
         let Some(setup) = self.setup_message(&message.channel_id, &message.id).await? else {
             return Ok(());
@@ -509,7 +1329,9 @@ impl Bot {
                     .await?;
             } else {
                 self.setup_messages.write().await.remove(&message.id);
-                let Some(role_message) = RoleMessage::parse(&content) else {
+                let Some(role_message) =
+                    setup.role_message(message.channel_id.clone(), &server, &content)?
+                else {
                     return Ok(());
                 };
                 self.check_role_message(&server.id, user_id, &role_message)
@@ -519,16 +1341,162 @@ impl Bot {
                     .http
                     .delete_message(&message.channel_id, &message.id)
                     .await;
-                let reply = SendableMessage::new()
-                    .content(content)
-                    .interactions(Interactions::new(emojis).restrict());
-                let response = self.http.send_message(message.channel_id, reply).await?;
-                self.role_messages
-                    .write()
-                    .await
-                    .insert(response.id, role_message);
+                self.send_role_message(content, emojis, role_message)
+                    .await?;
             }
         }
         Ok(())
     }
+
+    /// Sends a finished role message and starts tracking it.
+    pub async fn send_role_message(
+        &self,
+        content: String,
+        emojis: Vec<&str>,
+        role_message: RoleMessage,
+    ) -> Result<String, Error> {
+        let reply = SendableMessage::new()
+            .content(content)
+            .interactions(Interactions::new(emojis).restrict());
+        let response = self
+            .http
+            .send_message(&role_message.channel_id, reply)
+            .await?;
+        self.db
+            .save_role_message(&response.id, &role_message)
+            .await?;
+        self.role_messages
+            .write()
+            .await
+            .insert(response.id.clone(), role_message);
+        Ok(response.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROLE_ID: &str = "01HZX7Q3M9KJ8W2VN5RT6YBCDE";
+
+    fn server() -> Server {
+        serde_json::from_value(serde_json::json!({
+            "_id": "01HZX7Q3M9KJ8W2VN5RT6YBCDF",
+            "owner": "01HZX7Q3M9KJ8W2VN5RT6YBCDG",
+            "name": "Test",
+            "channels": [],
+            "default_permissions": 0,
+            "roles": {
+                ROLE_ID: {
+                    "_id": ROLE_ID,
+                    "name": "Red",
+                    "permissions": { "a": 0, "d": 0 },
+                    "hoist": false,
+                    "rank": 1
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    /// Sets up a message with `reaction` like `on_setup_react`, then parses the finished content.
+    /// Returns the content and the emoji the role is stored under.
+    fn round_trip(reaction: &str) -> (String, String) {
+        let setup = SetupMessage::parse("user".to_string(), "Pick {ROLE:Red} please").unwrap();
+        let content = setup.with_emojis(&[reaction], &server()).unwrap();
+        let role_message =
+            RoleMessage::parse("channel".to_string(), "server".to_string(), &content).unwrap();
+        let (emoji, role_ids) = role_message.roles.into_iter().next().unwrap();
+        assert_eq!(role_ids, [ROLE_ID]);
+        // `on_role_react` looks roles up by the key of the reaction it receives.
+        assert_eq!(emoji_key(reaction), emoji);
+        (content, emoji)
+    }
+
+    #[test]
+    fn setup_from_parts() {
+        let options = SetupOptions {
+            verify: true,
+            ..SetupOptions::default()
+        };
+        let text = "Exclusive roles, {ROLE:Blue}";
+        let roles = [("<%01HZX7Q3M9KJ8W2VN5RT6YBCDE>", None, "or {ROLE:Blue}")];
+        let setup = SetupMessage::from_parts("user".to_string(), options, text, &roles);
+        let content = setup.with_emojis(&["🔴"], &server()).unwrap();
+        assert_eq!(
+            content,
+            format!(
+                "[](VERIFY)Exclusive roles, {{ROLE:Blue}}\n:red_circle:[]({ROLE_ID}) __Red__ or {{ROLE:Blue}}"
+            )
+        );
+        let role_message =
+            RoleMessage::parse("channel".to_string(), "server".to_string(), &content).unwrap();
+        assert!(role_message.verify && !role_message.exclusive);
+        assert_eq!(role_message.roles["red_circle"], [ROLE_ID]);
+    }
+
+    #[test]
+    fn custom_emoji() {
+        let (content, emoji) = round_trip("01GAR9TW0FGMH680JM2C0P0Y02");
+        assert_eq!(emoji, "01GAR9TW0FGMH680JM2C0P0Y02");
+        assert_eq!(
+            content,
+            format!("Pick :01GAR9TW0FGMH680JM2C0P0Y02:[]({ROLE_ID}) __Red__ please")
+        );
+    }
+
+    #[test]
+    fn shortcode_emoji() {
+        let (content, emoji) = round_trip("🔴");
+        assert_eq!(emoji, "red_circle");
+        assert_eq!(
+            content,
+            format!("Pick :red_circle:[]({ROLE_ID}) __Red__ please")
+        );
+
+        let (content, emoji) = round_trip("👍");
+        assert_eq!(emoji, "+1");
+        assert_eq!(content, format!("Pick :+1:[]({ROLE_ID}) __Red__ please"));
+    }
+
+    #[test]
+    fn unicode_without_shortcode() {
+        // `EMOJI_PATTERN` matches pictographs, not flags or keycaps.
+        let pictograph = Regex::new(&format!("^{EMOJI_PATTERN}$")).unwrap();
+        let unnamed = emojis::iter()
+            .find(|e| e.shortcode().is_none() && pictograph.is_match(e.as_str()))
+            .expect("an emoji without a shortcode");
+        let (content, emoji) = round_trip(unnamed.as_str());
+        assert_eq!(emoji, unnamed.as_str());
+        assert_eq!(
+            content,
+            format!("Pick {}[]({ROLE_ID}) __Red__ please", unnamed.as_str())
+        );
+    }
+
+    #[test]
+    fn skin_tone() {
+        let (content, emoji) = round_trip("👍🏽");
+        assert_eq!(emoji, "👍🏽");
+        assert_ne!(emoji, emoji_key("👍"));
+        assert_eq!(content, format!("Pick 👍🏽[]({ROLE_ID}) __Red__ please"));
+    }
+
+    #[test]
+    fn zwj_sequence() {
+        // Skin tone and ZWJ, so there's no shortcode.
+        let (content, emoji) = round_trip("👩🏽‍💻");
+        assert_eq!(emoji, "👩🏽‍💻");
+        assert_eq!(content, format!("Pick 👩🏽‍💻[]({ROLE_ID}) __Red__ please"));
+
+        round_trip("👩‍💻");
+    }
+
+    #[test]
+    fn variation_selector() {
+        let (content, emoji) = round_trip("❤️");
+        assert_eq!(emoji, "heart");
+        assert_eq!(content, format!("Pick :heart:[]({ROLE_ID}) __Red__ please"));
+        assert_eq!(emoji_key("❤"), "heart");
+    }
 }
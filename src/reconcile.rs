@@ -0,0 +1,223 @@
+use std::collections::{HashMap, HashSet};
+
+use volty::prelude::*;
+
+use crate::{
+    Bot,
+    error::Error,
+    reaction::{RoleAction, RoleMessage, emoji_key},
+};
+
+// Max members queued per server in a single reconciliation pass.
+const RECONCILE_BUDGET: usize = 250;
+
+impl Bot {
+    /// Queues role edits for reactions added or removed while the bot was offline.
+    /// Reactions are compared with the ones last seen, so roles from other sources are left alone.
+    pub async fn reconcile_role_messages(&self) {
+        let role_messages = match self.db.get_role_messages().await {
+            Ok(role_messages) => role_messages,
+            Err(e) => {
+                dbg!(e);
+                return;
+            }
+        };
+        let mut servers: HashMap<String, Vec<(String, RoleMessage)>> = HashMap::new();
+        for (message_id, role_message) in role_messages {
+            servers
+                .entry(role_message.server_id.clone())
+                .or_default()
+                .push((message_id, role_message));
+        }
+
+        let mut messages = 0;
+        let mut queued = 0;
+        let mut skipped = 0;
+        for (server_id, role_messages) in &servers {
+            match self.reconcile_server(server_id, role_messages).await {
+                Ok((q, s)) => {
+                    messages += role_messages.len();
+                    queued += q;
+                    skipped += s;
+                }
+                Err(e) => {
+                    dbg!(server_id, e);
+                }
+            }
+        }
+        println!(
+            "Reconciled {messages} role messages in {} servers: {queued} members queued, {skipped} over budget left for the next pass",
+            servers.len()
+        );
+    }
+
+    async fn reconcile_server(
+        &self,
+        server_id: &str,
+        role_messages: &[(String, RoleMessage)],
+    ) -> Result<(usize, usize), Error> {
+        let Some(server) = self.cache.get_server(server_id).await else {
+            return Ok((0, 0));
+        };
+        let my_id = self.cache.user_id();
+        self.check_server_perms(server_id, my_id, &[Permission::AssignRoles])
+            .await?;
+        let bot_member = self
+            .cache
+            .fetch_member(&self.http, server_id, my_id)
+            .await?;
+        let bot_rank = bot_member.rank(&server);
+        let members = self.http.fetch_members(server_id).await?.members;
+        let members: HashMap<&str, &Member> =
+            members.iter().map(|m| (m.id.user.as_str(), m)).collect();
+
+        // Reactions now and when the bot last saw the message, as (Emoji, UserID).
+        let mut messages = Vec::with_capacity(role_messages.len());
+        for (message_id, role_message) in role_messages {
+            let message = match self
+                .cache
+                .fetch_message(&self.http, &role_message.channel_id, message_id)
+                .await
+            {
+                Ok(message) => message,
+                Err(e) => {
+                    dbg!(message_id, e);
+                    continue;
+                }
+            };
+            let mut current: HashSet<(String, String)> = HashSet::new();
+            for (emoji, user_ids) in &message.reactions {
+                let emoji = emoji_key(emoji);
+                if !role_message.roles.contains_key(emoji) {
+                    continue;
+                }
+                for user_id in user_ids {
+                    current.insert((emoji.to_string(), user_id.clone()));
+                }
+            }
+            let seen = self.db.get_reactors(message_id).await?;
+            messages.push((message_id, role_message, current, seen));
+        }
+
+        // k=(Group, UserID), v=Emojis reacted to across the group's messages
+        let mut group_reacted: HashMap<(&str, &str), usize> = HashMap::new();
+        // k=UserID, v=Roles still given by a reaction on any message
+        let mut kept: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for (_, role_message, current, _) in &messages {
+            for (emoji, user_id) in current {
+                if let Some(group) = &role_message.server_group {
+                    *group_reacted
+                        .entry((group.as_str(), user_id.as_str()))
+                        .or_default() += 1;
+                }
+                if !role_message.reverse {
+                    let roles = role_message.roles[emoji].iter().map(String::as_str);
+                    kept.entry(user_id.as_str()).or_default().extend(roles);
+                }
+            }
+        }
+
+        let mut actions: HashMap<String, RoleAction> = HashMap::new();
+        for (_, role_message, current, seen) in &messages {
+            // k=UserID, v=Emojis reacted to
+            let mut reacted: HashMap<&str, Vec<&str>> = HashMap::new();
+            for (emoji, user_id) in current {
+                reacted.entry(user_id.as_str()).or_default().push(emoji);
+            }
+
+            let added = current.difference(seen).map(|r| (r, true));
+            let removed = seen.difference(current).map(|r| (r, false));
+            for ((emoji, user_id), is_added) in added.chain(removed) {
+                let Some(role_ids) = role_message.roles.get(emoji) else {
+                    continue;
+                };
+                let Some(member) = members.get(user_id.as_str()) else {
+                    continue;
+                };
+                if member.rank(&server) <= bot_rank {
+                    continue;
+                }
+                let wanted = reacted.get(user_id.as_str()).map_or(&[][..], Vec::as_slice);
+                let is_group_conflict = role_message.server_group.as_ref().is_some_and(|g| {
+                    group_reacted
+                        .get(&(g.as_str(), user_id.as_str()))
+                        .is_some_and(|&count| count > 1)
+                });
+                let is_conflict = is_group_conflict
+                    || role_message.is_conflict(wanted)
+                    || role_message.limit.is_some_and(|max| wanted.len() > max);
+                if is_added && is_conflict {
+                    continue;
+                }
+                if !is_added && role_message.verify {
+                    continue;
+                }
+                // Same as a live reaction, reverse mode swaps giving and taking.
+                let is_give = is_added != role_message.reverse;
+                let has_requires = role_message
+                    .requires
+                    .as_ref()
+                    .is_none_or(|r| member.roles.contains(r));
+                // Expired roles keep their reaction, so they aren't given again.
+                let is_expiring = role_message.expires.is_some() && !role_message.reverse;
+                if is_give && (!has_requires || is_expiring) {
+                    continue;
+                }
+                let action = actions
+                    .entry(user_id.clone())
+                    .or_insert_with(|| RoleAction {
+                        give: Vec::new(),
+                        remove: Vec::new(),
+                        limit: None,
+                    });
+                for role_id in role_ids {
+                    if !server.roles.get(role_id).is_some_and(|r| r.rank > bot_rank) {
+                        continue;
+                    }
+                    let has_role = member.roles.contains(role_id);
+                    let is_kept = kept
+                        .get(user_id.as_str())
+                        .is_some_and(|k| k.contains(role_id.as_str()));
+                    if is_give && !has_role {
+                        action.give.push(role_id.clone());
+                    } else if !is_give && has_role && !is_kept {
+                        action.remove.push(role_id.clone());
+                    }
+                }
+            }
+        }
+        // Two messages can share a role, giving wins over taking.
+        for action in actions.values_mut() {
+            action.give.sort();
+            action.give.dedup();
+            action.remove.sort();
+            action.remove.dedup();
+            action.remove.retain(|r| !action.give.contains(r));
+        }
+
+        actions.retain(|_, a| !a.give.is_empty() || !a.remove.is_empty());
+        let mut actions: Vec<(String, RoleAction)> = actions.into_iter().collect();
+        actions.sort_by(|a, b| a.0.cmp(&b.0));
+        // Members over budget keep their last seen reactions, so the next pass picks them up.
+        let over_budget = actions.split_off(actions.len().min(RECONCILE_BUDGET));
+        let skipped: HashSet<&str> = over_budget.iter().map(|(u, _)| u.as_str()).collect();
+        let queued = actions.len();
+        for (user_id, action) in actions {
+            println!(
+                "reconcile: Server: {server_id}, Member: {user_id}, Give: {:?}, Take: {:?}",
+                &action.give, &action.remove
+            );
+            self.queue_edit(server_id, user_id, action).await;
+        }
+        for (message_id, _, current, seen) in &messages {
+            let reactors: HashSet<(String, String)> = current
+                .iter()
+                .filter(|(_, u)| !skipped.contains(u.as_str()))
+                .chain(seen.iter().filter(|(_, u)| skipped.contains(u.as_str())))
+                .cloned()
+                .collect();
+            self.db.save_reactors(message_id, &reactors).await?;
+        }
+        Ok((queued, skipped.len()))
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, atomic::Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::time::sleep;
+use volty::prelude::*;
+
+use crate::{
+    Bot,
+    error::Error,
+    reaction::{RoleAction, is_permanent},
+};
+
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Failures that can pass, like server errors or a busy database.
+fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::Http(e) => !is_permanent(e),
+        // `run_scheduled` only gets custom errors from the database.
+        Error::Custom(_) => true,
+        _ => false,
+    }
+}
+
+impl Bot {
+    /// Queues scheduled roles and expiries once they are due, both are stored in the database.
+    pub async fn run_scheduler(self: Arc<Self>) {
+        // Servers need to be cached before roles can be checked.
+        let _ = self.ready.subscribe().wait_for(|&ready| ready).await;
+        while !self.closing.load(Ordering::SeqCst) {
+            self.run_due_roles().await;
+            sleep(SCHEDULER_INTERVAL).await;
+        }
+    }
+
+    async fn run_due_roles(&self) {
+        let due = match self.db.get_due_roles(unix_time()).await {
+            Ok(due) => due,
+            Err(e) => {
+                dbg!(e);
+                return;
+            }
+        };
+        if due.is_empty() {
+            return;
+        }
+
+        // k=(ServerID, UserID), v=(Scheduled IDs, Action)
+        let mut members: HashMap<(String, String), (Vec<i64>, RoleAction)> = HashMap::new();
+        for (id, server_id, user_id, role_id, give) in due {
+            let (ids, action) = members.entry((server_id, user_id)).or_insert_with(|| {
+                let action = RoleAction {
+                    give: Vec::new(),
+                    remove: Vec::new(),
+                    limit: None,
+                };
+                (Vec::new(), action)
+            });
+            ids.push(id);
+            if give {
+                action.give.push(role_id);
+            } else {
+                action.remove.push(role_id);
+            }
+        }
+        let mut finished = Vec::new();
+        for ((server_id, user_id), (ids, action)) in members {
+            match self.run_scheduled(&server_id, &user_id, action).await {
+                Ok(()) => {}
+                // Left in the database for the next tick.
+                Err(e) if is_transient(&e) => {
+                    dbg!(&server_id, &user_id, e);
+                    continue;
+                }
+                Err(e) => {
+                    dbg!(&server_id, &user_id, e);
+                }
+            }
+            finished.extend(ids);
+        }
+        if finished.is_empty() {
+            return;
+        }
+        if let Err(e) = self.db.finish_scheduled_roles(&finished).await {
+            dbg!(e);
+        }
+    }
+
+    async fn run_scheduled(
+        &self,
+        server_id: &str,
+        user_id: &str,
+        mut action: RoleAction,
+    ) -> Result<(), Error> {
+        if !action.remove.is_empty() {
+            // Expired roles shouldn't come back if the member left and rejoins.
+            self.db
+                .forget_sticky_roles(server_id, user_id, &action.remove)
+                .await?;
+        }
+        let Some(server) = self.cache.get_server(server_id).await else {
+            return Ok(());
+        };
+        action
+            .give
+            .retain(|role_id| server.roles.contains_key(role_id));
+        action
+            .remove
+            .retain(|role_id| server.roles.contains_key(role_id));
+        if action.give.is_empty() && action.remove.is_empty() {
+            return Ok(());
+        }
+
+        let my_id = self.cache.user_id();
+        self.check_server_perms(server_id, my_id, &[Permission::AssignRoles])
+            .await?;
+        let role_ids = action.give.iter().chain(&action.remove);
+        self.check_above_roles(server_id, my_id, role_ids.map(|s| s.as_str()))
+            .await?;
+
+        println!(
+            "Scheduled: {server_id}, {user_id}, Give: {:?}, Take: {:?}",
+            &action.give, &action.remove
+        );
+        self.queue_edit(server_id, user_id.to_string(), action)
+            .await;
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt::Write;
+
+use volty::prelude::*;
+
+use crate::{
+    Bot, constants::HELP_STICKY_MESSAGE, database::ServerSettings, error::Error, strip_role_mention,
+};
+
+impl Bot {
+    /// Members are removed from the cache when they leave, so save them first.
+    pub async fn before_cache_update(&self, event: &ServerEvent) {
+        let ServerEvent::ServerMemberLeave { id, user, .. } = event else {
+            return;
+        };
+        if !self.db.get_settings(id).await.is_some_and(|s| s.sticky) {
+            return;
+        }
+        match self.cache.get_member(id, user).await {
+            Some(member) => {
+                self.leaving_members
+                    .write()
+                    .await
+                    .insert((id.clone(), user.clone()), member);
+            }
+            None => println!("Sticky: {id}, {user}, member wasn't cached, roles not saved"),
+        }
+    }
+
+    pub async fn remember_roles(&self, server_id: &str, user_id: &str) -> Result<(), Error> {
+        let key = (server_id.to_string(), user_id.to_string());
+        let Some(member) = self.leaving_members.write().await.remove(&key) else {
+            return Ok(());
+        };
+        let Some(settings) = self.db.get_settings(server_id).await else {
+            return Ok(());
+        };
+        if !settings.sticky {
+            return Ok(());
+        }
+        let Some(server) = self.cache.get_server(server_id).await else {
+            return Ok(());
+        };
+        let bot_member = self
+            .cache
+            .fetch_member(&self.http, server_id, self.cache.user_id())
+            .await?;
+        let bot_rank = bot_member.rank(&server);
+
+        let roles: Vec<String> = member
+            .roles
+            .into_iter()
+            .filter(|role_id| !settings.sticky_exclude.contains(role_id))
+            .filter(|role_id| {
+                server
+                    .roles
+                    .get(role_id)
+                    .is_some_and(|role| role.rank > bot_rank)
+            })
+            .collect();
+        if roles.is_empty() {
+            return Ok(());
+        }
+        println!("Sticky: {server_id}, {user_id}, {:?}", &roles);
+        self.db
+            .save_sticky_roles(server_id, user_id, &roles)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn sticky_command(&self, message: &Message, args: &str) -> Result<(), Error> {
+        let Some(server) = self.get_server(&message.channel_id).await else {
+            return Ok(());
+        };
+        let mut settings = self
+            .db
+            .get_settings(&server.id)
+            .await
+            .unwrap_or_else(|| ServerSettings::new(server.id.clone()));
+        let (command, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let rest = rest.trim();
+        let command = command.to_lowercase();
+        let is_valid = match command.as_str() {
+            "on" | "off" => true,
+            "exclude" => !rest.is_empty(),
+            _ => false,
+        };
+        if !is_valid {
+            let mut send = HELP_STICKY_MESSAGE.replace("%BOT_MENTION%", self.cache.user_mention());
+            let status = if settings.sticky { "on" } else { "off" };
+            write!(send, "\nSticky roles are {status}").unwrap();
+            if !settings.sticky_exclude.is_empty() {
+                write!(send, "\nExcluded Roles:").unwrap();
+                for role in &settings.sticky_exclude {
+                    let name = server.roles.get(role).map(|r| &r.name).unwrap_or(role);
+                    write!(send, "\n`{name}`").unwrap();
+                }
+            }
+            self.http.send_message(&message.channel_id, send).await?;
+            return Ok(());
+        }
+
+        self.check_server_perms(&server.id, self.cache.user_id(), &[Permission::AssignRoles])
+            .await?;
+        self.check_server_perms(
+            &server.id,
+            &message.author_id,
+            &[Permission::AssignRoles, Permission::ManageServer],
+        )
+        .await?;
+
+        let send = match command.as_str() {
+            "on" => {
+                settings.sticky = true;
+                "Sticky roles enabled!".to_string()
+            }
+            "off" => {
+                settings.sticky = false;
+                self.db.clear_sticky_roles(&server.id).await?;
+                "Sticky roles disabled!".to_string()
+            }
+            _ => {
+                let role_id_or_name = strip_role_mention(rest);
+                let Some((role_id, role)) = server.role_by_id_or_name(role_id_or_name) else {
+                    return Err(Error::InvalidRole(role_id_or_name.to_string()));
+                };
+                if let Some(index) = settings.sticky_exclude.iter().position(|r| r == role_id) {
+                    settings.sticky_exclude.remove(index);
+                    format!("`{}` is no longer excluded from sticky roles!", role.name)
+                } else {
+                    settings.sticky_exclude.push(role_id.to_string());
+                    format!("`{}` excluded from sticky roles!", role.name)
+                }
+            }
+        };
+        self.db.save_settings(settings).await?;
+        self.http.send_message(&message.channel_id, send).await?;
+        Ok(())
+    }
+}
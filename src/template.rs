@@ -0,0 +1,369 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+use volty::prelude::*;
+
+use crate::{
+    Bot,
+    constants::{HELP_CREATE_MESSAGE, HELP_EXPORT_MESSAGE},
+    error::Error,
+    format_duration, parse_duration, parse_message_link,
+    reaction::{
+        EMOJI_PATTERN, SetupMessage, SetupOptions, reaction_emoji, role_name_or_id, valid_group,
+    },
+};
+
+// Attached templates larger than this aren't downloaded.
+const MAX_TEMPLATE_SIZE: usize = 32 * 1024;
+// Downloads of attached templates are given up after this long.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A role message written as TOML or JSON, fields match the setup keywords.
+#[derive(Deserialize, Serialize)]
+struct Template {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    text: String,
+    #[serde(default, skip_serializing_if = "is_false")]
+    exclusive: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    formatted: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    verify: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    reverse: bool,
+    // Duration like `2h`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    requires: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+    // Server wide exclusive group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    roles: Vec<TemplateRole>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct TemplateRole {
+    emoji: String,
+    // Role names or IDs, comma separated
+    role: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    text: String,
+    // Exclusive group within the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Toml => "toml",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// Takes the document out of a code block, the language picks the format.
+fn parse_template(args: &str) -> Result<Template, String> {
+    static RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"(?s)^```([a-zA-Z]*)\s*\n(.*?)```$").unwrap());
+    let args = args.trim();
+    let (language, document) = match RE.captures(args) {
+        Some(captures) => (
+            captures.get(1).unwrap().as_str().to_lowercase(),
+            captures.get(2).unwrap().as_str(),
+        ),
+        None => (String::new(), args),
+    };
+    let format = match language.as_str() {
+        "json" => Format::Json,
+        "toml" => Format::Toml,
+        _ if document.trim_start().starts_with('{') => Format::Json,
+        _ => Format::Toml,
+    };
+    parse_document(document, format)
+}
+
+fn parse_document(document: &str, format: Format) -> Result<Template, String> {
+    match format {
+        Format::Toml => toml::from_str(document).map_err(|e| e.to_string()),
+        Format::Json => serde_json::from_str(document).map_err(|e| e.to_string()),
+    }
+}
+
+/// Templates can be attached as a `.toml` or `.json` file instead of a code block.
+fn attachment_format(filename: &str) -> Option<Format> {
+    let filename = filename.to_lowercase();
+    if filename.ends_with(".toml") {
+        Some(Format::Toml)
+    } else if filename.ends_with(".json") {
+        Some(Format::Json)
+    } else {
+        None
+    }
+}
+
+impl Bot {
+    pub async fn create_command(&self, message: &Message, args: &str) -> Result<(), Error> {
+        let Some(server) = self.get_server(&message.channel_id).await else {
+            return Ok(());
+        };
+        let attachment = message
+            .attachments
+            .iter()
+            .flatten()
+            .find_map(|file| Some((&file.id, attachment_format(&file.filename)?)));
+        let template = match (args.is_empty(), attachment) {
+            (true, Some((file_id, format))) => {
+                let document = self.download_template(file_id).await?;
+                parse_document(&document, format)
+            }
+            (true, None) => {
+                let send = HELP_CREATE_MESSAGE.replace("%BOT_MENTION%", self.cache.user_mention());
+                self.http.send_message(&message.channel_id, send).await?;
+                return Ok(());
+            }
+            (false, _) => parse_template(args),
+        };
+        let template = match template {
+            Ok(template) => template,
+            Err(e) => return Err(Error::Custom(format!("Invalid template!\n{e}"))),
+        };
+        if template.roles.is_empty() {
+            return Err(Error::Custom("Templates need at least 1 role!".to_string()));
+        }
+        if template.roles.len() > 20 {
+            return Err(Error::Custom(
+                "No more than 20 roles per message!".to_string(),
+            ));
+        }
+        if template
+            .roles
+            .iter()
+            .any(|r| r.role.is_empty() || r.role.contains(['{', '}', '|']))
+        {
+            return Err(Error::Custom("Invalid role in template!".to_string()));
+        }
+        if !template
+            .roles
+            .iter()
+            .flat_map(|r| &r.group)
+            .chain(&template.group)
+            .all(|g| valid_group(g).is_some())
+        {
+            return Err(Error::Custom(
+                "Groups can only use letters, numbers, `_` and `-`!".to_string(),
+            ));
+        }
+
+        let expires = match template.expires.as_deref().map(parse_duration) {
+            Some(None) => return Err(Error::Custom("Invalid `expires` in template!".to_string())),
+            expires => expires.flatten(),
+        };
+        if template
+            .requires
+            .as_ref()
+            .is_some_and(|r| r.is_empty() || r.contains(['{', '}', '|', ',']))
+        {
+            return Err(Error::Custom("Invalid `requires` in template!".to_string()));
+        }
+        if template.limit == Some(0) {
+            return Err(Error::Custom("`limit` must be at least 1!".to_string()));
+        }
+
+        // Built from the fields, so the text is never read as keywords or roles.
+        let options = SetupOptions {
+            exclusive: template.exclusive,
+            formatted: template.formatted,
+            verify: template.verify,
+            reverse: template.reverse,
+            expires,
+            requires: template.requires.as_deref().map(role_name_or_id),
+            limit: template.limit,
+            server_group: template.group.as_deref().and_then(valid_group),
+        };
+        let roles: Vec<(&str, Option<&str>, &str)> = template
+            .roles
+            .iter()
+            .map(|r| (r.role.as_str(), r.group.as_deref(), r.text.as_str()))
+            .collect();
+        let setup =
+            SetupMessage::from_parts(message.author_id.clone(), options, &template.text, &roles);
+        self.check_setup_message(&server.id, &message.author_id, &setup)
+            .await?;
+
+        let emojis: Vec<&str> = template
+            .roles
+            .iter()
+            .map(|r| reaction_emoji(&r.emoji))
+            .collect();
+        let Some(content) = setup.with_emojis(&emojis, &server) else {
+            return Err(Error::Custom("Invalid role in template!".to_string()));
+        };
+        if content.len() > 2_000 {
+            return Err(Error::Custom("Message would be too long!".to_string()));
+        }
+        let Some(role_message) =
+            setup.role_message(message.channel_id.clone(), &server, &content)?
+        else {
+            return Err(Error::Custom("Invalid emoji in template!".to_string()));
+        };
+        if role_message.roles.len() != emojis.len() {
+            return Err(Error::Custom("Emojis must be different!".to_string()));
+        }
+        self.check_role_message(&server.id, &message.author_id, &role_message)
+            .await?;
+
+        let message_id = self
+            .send_role_message(content, emojis, role_message)
+            .await?;
+        println!(
+            "Create: Server: {}, Message: {message_id}, By: {}",
+            &server.id, &message.author_id
+        );
+        Ok(())
+    }
+
+    async fn download_template(&self, file_id: &str) -> Result<String, Error> {
+        static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+            reqwest::Client::builder()
+                .timeout(DOWNLOAD_TIMEOUT)
+                .build()
+                .unwrap()
+        });
+        let api_info = self.cache.api_info(&self.http).await?;
+        let url = format!("{}/attachments/{file_id}", api_info.features.autumn.url);
+        // Read in chunks so files over the limit are never held whole, None if too big.
+        let download = async {
+            let mut response = CLIENT.get(&url).send().await?.error_for_status()?;
+            if response
+                .content_length()
+                .is_some_and(|len| len > MAX_TEMPLATE_SIZE as u64)
+            {
+                return Ok(None);
+            }
+            let mut document = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                if document.len() + chunk.len() > MAX_TEMPLATE_SIZE {
+                    return Ok(None);
+                }
+                document.extend_from_slice(&chunk);
+            }
+            Ok::<_, reqwest::Error>(Some(document))
+        };
+        let document = match download.await {
+            Ok(Some(document)) => document,
+            Ok(None) => return Err(Error::Custom("Template file is too big!".to_string())),
+            Err(e) => {
+                dbg!(e);
+                return Err(Error::Custom("Couldn't download the template!".to_string()));
+            }
+        };
+        String::from_utf8(document)
+            .map_err(|_| Error::Custom("Template file must be text!".to_string()))
+    }
+
+    pub async fn export_command(&self, message: &Message, args: &str) -> Result<(), Error> {
+        // The emoji and role of a finished role message, with the role name if not formatted.
+        static RE_ROLE: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(&format!(
+                r"(?i){EMOJI_PATTERN}\[]\(([0-9A-HJKMNP-TV-Z]{{26}}(?:,[0-9A-HJKMNP-TV-Z]{{26}})*)(?:#([a-z0-9_-]+))?\)(?: __[^\n]*?__)?"
+            ))
+            .unwrap()
+        });
+        let Some(server) = self.get_server(&message.channel_id).await else {
+            return Ok(());
+        };
+        let mut args = args.split_ascii_whitespace();
+        let link = args.next().and_then(parse_message_link);
+        let format = match args.next().map(|a| a.to_lowercase()).as_deref() {
+            None | Some("toml") => Some(Format::Toml),
+            Some("json") => Some(Format::Json),
+            _ => None,
+        };
+        let (Some((link_channel_id, message_id)), Some(format)) = (link, format) else {
+            let send = HELP_EXPORT_MESSAGE.replace("%BOT_MENTION%", self.cache.user_mention());
+            self.http.send_message(&message.channel_id, send).await?;
+            return Ok(());
+        };
+        let (channel_id, role_message) = self
+            .find_role_message(&server.id, link_channel_id, message_id, &message.channel_id)
+            .await?;
+        let not_found = || Error::Custom("Role message not found!".to_string());
+        let target = self
+            .cache
+            .fetch_message(&self.http, &channel_id, message_id)
+            .await?;
+        let Some(mut content) = target.content.as_deref() else {
+            return Err(not_found());
+        };
+
+        for mode in ["[](EXCLUSIVE)", "[](VERIFY)", "[](REVERSE)"] {
+            content = content.strip_prefix(mode).unwrap_or(content);
+        }
+        let mut template = Template {
+            text: String::new(),
+            exclusive: role_message.exclusive,
+            formatted: true,
+            verify: role_message.verify,
+            reverse: role_message.reverse,
+            expires: role_message.expires.map(format_duration),
+            requires: role_message.requires.clone(),
+            limit: role_message.limit,
+            group: role_message.server_group.clone(),
+            roles: Vec::new(),
+        };
+        let mut last_end = 0;
+        for captures in RE_ROLE.captures_iter(content) {
+            let range = captures.get(0).unwrap().range();
+            let text = content[last_end..range.start].trim().to_string();
+            match template.roles.last_mut() {
+                Some(role) => role.text = text,
+                None => template.text = text,
+            }
+            last_end = range.end;
+            if captures.get(0).unwrap().as_str().ends_with("__") {
+                template.formatted = false;
+            }
+            let emoji = reaction_emoji(captures.get(1).unwrap().as_str());
+            template.roles.push(TemplateRole {
+                emoji: emoji.to_string(),
+                role: captures.get(2).unwrap().as_str().to_string(),
+                text: String::new(),
+                group: captures.get(3).map(|g| g.as_str().to_string()),
+            });
+        }
+        let text = content[last_end..].trim().to_string();
+        match template.roles.last_mut() {
+            Some(role) => role.text = text,
+            None => return Err(not_found()),
+        }
+
+        let document = match format {
+            Format::Toml => toml::to_string(&template).map_err(|e| e.to_string()),
+            Format::Json => serde_json::to_string_pretty(&template).map_err(|e| e.to_string()),
+        };
+        let document = match document {
+            Ok(document) => document,
+            Err(e) => return Err(Error::Custom(format!("Couldn't export message!\n{e}"))),
+        };
+        let send = format!("```{}\n{document}\n```", format.name());
+        if send.len() > 2_000 {
+            return Err(Error::Custom("Export is too long to send!".to_string()));
+        }
+        self.http.send_message(&message.channel_id, send).await?;
+        Ok(())
+    }
+}